@@ -1,13 +1,90 @@
+use crate::api::health::ReadinessConfig;
 use crate::elevation::ElevationService;
-use crate::{otp::OtpCluster, valhalla::ValhallaRouter, Error, Result};
+use crate::{
+    otp::OtpApi, otp::OtpCluster, otp::RouterSelection, valhalla::ValhallaRouter, Error, Result,
+};
+use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 use url::Url;
 
 #[derive(Debug, Clone)]
 pub struct AppState {
-    otp_cluster: OtpCluster,
+    /// Swapped out wholesale whenever routers are refreshed, so in-flight requests keep a
+    /// consistent view of the cluster.
+    otp_cluster: Arc<RwLock<Arc<OtpCluster>>>,
     valhalla_router: ValhallaRouter,
     elevation: ElevationService,
+    upstream_config: UpstreamConfig,
+    readiness_config: ReadinessConfig,
+    /// OTP's GraphQL API needs absolute times, so we remember each router's time zone once
+    /// we've looked it up.
+    otp_time_zones: Arc<RwLock<HashMap<Url, chrono_tz::Tz>>>,
+    /// Shared by every request to an upstream service, so that connections are reused.
+    http_client: reqwest::Client,
+}
+
+/// How we talk to the routing services behind travelmux.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpstreamConfig {
+    /// How long to wait for a response from OTP before giving up.
+    pub otp_timeout: Duration,
+    /// How long to wait for a response from Valhalla before giving up.
+    pub valhalla_timeout: Duration,
+    /// For walking and cycling trips, query Valhalla at the same time as OTP rather than only
+    /// after OTP fails, so a slow or failing OTP doesn't delay the fallback.
+    pub race_otp_and_valhalla: bool,
+    /// When racing, how much longer to wait for OTP once Valhalla has planned a trip.
+    pub otp_race_grace: Duration,
+    /// When several OTP routers cover a trip and the preferred one finds no path, try the others
+    /// in order rather than reporting the failure.
+    pub fall_back_to_next_otp_router: bool,
+    /// For a transit trip ending outside its origin's OTP router, how far the destination may
+    /// be from that router for us to plan transit to its edge and the rest of the way by
+    /// another mode.
+    pub max_cross_router_gap_meters: f64,
+    /// How many idle connections to keep open to each upstream host.
+    pub pool_max_idle_per_host: usize,
+    /// How long an idle connection is kept open before being closed.
+    pub pool_idle_timeout: Duration,
+    /// Ask upstream services to gzip their responses.
+    pub gzip: bool,
+    /// The time zone of OTP routers using the GraphQL API whose agencies' time zone can't be
+    /// looked up, for planning trips at a requested local time.
+    pub otp_time_zone: Option<chrono_tz::Tz>,
+}
+
+impl UpstreamConfig {
+    fn http_client(&self) -> reqwest::Client {
+        reqwest::Client::builder()
+            .user_agent(concat!("travelmux/", env!("CARGO_PKG_VERSION")))
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .pool_idle_timeout(self.pool_idle_timeout)
+            .tcp_keepalive(Duration::from_secs(60))
+            .gzip(self.gzip)
+            // Requests should set their own timeout, this is just a backstop for any that don't.
+            .timeout(self.otp_timeout.max(self.valhalla_timeout))
+            .build()
+            .expect("valid http client configuration")
+    }
+}
+
+impl Default for UpstreamConfig {
+    fn default() -> Self {
+        Self {
+            otp_timeout: Duration::from_secs(10),
+            valhalla_timeout: Duration::from_secs(10),
+            race_otp_and_valhalla: false,
+            otp_race_grace: Duration::from_millis(500),
+            fall_back_to_next_otp_router: false,
+            max_cross_router_gap_meters: 10_000.0,
+            pool_max_idle_per_host: 32,
+            pool_idle_timeout: Duration::from_secs(90),
+            gzip: false,
+            otp_time_zone: None,
+        }
+    }
 }
 
 impl AppState {
@@ -15,33 +92,157 @@ impl AppState {
         log::info!("new AppState with valhalla_endpoint: {valhalla_endpoint:?}");
         let valhalla_router = ValhallaRouter::new(valhalla_endpoint);
         debug_assert!(std::fs::exists(&tif_dir).unwrap());
+        let upstream_config = UpstreamConfig::default();
         Self {
             valhalla_router,
-            otp_cluster: OtpCluster::default(),
+            otp_cluster: Arc::new(RwLock::new(Arc::new(OtpCluster::default()))),
             elevation: ElevationService::new(tif_dir),
+            http_client: upstream_config.http_client(),
+            upstream_config,
+            readiness_config: ReadinessConfig::default(),
+            otp_time_zones: Arc::default(),
         }
     }
 
-    pub async fn add_otp_endpoint(&mut self, endpoint: &str) -> Result<()> {
+    pub fn set_upstream_config(&mut self, upstream_config: UpstreamConfig) {
+        log::info!("using upstream config: {upstream_config:?}");
+        self.http_client = upstream_config.http_client();
+        self.upstream_config = upstream_config;
+    }
+
+    pub fn set_readiness_config(&mut self, readiness_config: ReadinessConfig) {
+        log::info!("using readiness config: {readiness_config:?}");
+        self.readiness_config = readiness_config;
+    }
+
+    pub fn set_otp_router_selection(&self, selection: RouterSelection) {
+        log::info!("using OTP router selection: {selection:?}");
+        self.update_otp_cluster(|cluster| cluster.set_router_selection(selection));
+    }
+
+    /// Register an OTP endpoint and fetch its routers. See `OtpApi::parse_endpoint` for how to
+    /// choose which API to plan trips with.
+    ///
+    /// If the routers can't be fetched, the endpoint stays registered as pending, and it will
+    /// be retried by `spawn_otp_router_refresh`.
+    pub async fn add_otp_endpoint(&self, endpoint: &str) -> Result<()> {
         log::info!("adding endpoint: {endpoint}");
+        let (api, endpoint) = OtpApi::parse_endpoint(endpoint);
         let url = Url::parse(endpoint).map_err(|err| {
             log::error!("error while parsing endpoint url {endpoint:?}");
             Error::server(format!("invalid endpoint url: {err}"))
         })?;
+        self.update_otp_cluster(|cluster| cluster.register_endpoint(url.clone(), api));
 
-        // TODO: Separate inserting an endpoint from (periodically) fetching its routers
-        self.otp_cluster
-            .insert_endpoint(url)
+        let routers = OtpCluster::fetch_routers(&url, &self.http_client)
             .await
             .inspect_err(|err| {
                 log::error!("error while inserting endpoint {endpoint:?}, {err}");
             })?;
+        self.update_otp_cluster(|cluster| cluster.set_endpoint_routers(url, routers));
         log::info!("added endpoint: {endpoint}");
         Ok(())
     }
 
-    pub fn otp_cluster(&self) -> &OtpCluster {
-        &self.otp_cluster
+    /// Re-fetch the routers of every OTP endpoint, e.g. to pick up a newly deployed graph.
+    ///
+    /// If an endpoint can't be reached, we keep serving its previously fetched routers.
+    pub async fn refresh_otp_routers(&self) {
+        let endpoints = self.otp_cluster().endpoints().cloned().collect();
+        self.fetch_otp_routers(endpoints).await;
+        log::debug!(
+            "refreshed OTP routers - there are {} routers.",
+            self.otp_cluster().router_len()
+        );
+    }
+
+    /// Retry fetching the routers of any OTP endpoints which have never loaded.
+    pub async fn retry_pending_otp_endpoints(&self) {
+        let pending = self.otp_cluster().pending_endpoints().cloned().collect();
+        self.fetch_otp_routers(pending).await;
+    }
+
+    async fn fetch_otp_routers(&self, endpoints: Vec<Url>) {
+        let mut fetched = Vec::with_capacity(endpoints.len());
+        for endpoint in endpoints {
+            match OtpCluster::fetch_routers(&endpoint, &self.http_client).await {
+                Ok(routers) => fetched.push((endpoint, routers)),
+                Err(err) => {
+                    log::warn!("failed to fetch routers for {endpoint}: {err}")
+                }
+            }
+        }
+
+        self.update_otp_cluster(|cluster| {
+            for (endpoint, routers) in fetched {
+                cluster.set_endpoint_routers(endpoint, routers);
+            }
+        });
+    }
+
+    /// Periodically refresh the OTP routers in the background, for as long as the server runs.
+    ///
+    /// Pending endpoints are retried more often - every `retry_interval` - until they load.
+    /// A zero `refresh_interval` disables refreshing endpoints which have already loaded.
+    pub fn spawn_otp_router_refresh(&self, refresh_interval: Duration, retry_interval: Duration) {
+        if self.otp_cluster().pending_endpoints().next().is_some() {
+            let app_state = self.clone();
+            actix_web::rt::spawn(async move {
+                let mut interval = actix_web::rt::time::interval(retry_interval);
+                // The first tick completes immediately, but we've only just tried.
+                interval.tick().await;
+                while app_state.otp_cluster().pending_endpoints().next().is_some() {
+                    interval.tick().await;
+                    app_state.retry_pending_otp_endpoints().await;
+                }
+                log::info!(
+                    "all OTP endpoints loaded - there are {} routers.",
+                    app_state.otp_cluster().router_len()
+                );
+            });
+        }
+
+        if refresh_interval.is_zero() {
+            log::info!("periodic OTP router refresh is disabled");
+            return;
+        }
+        let app_state = self.clone();
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(refresh_interval);
+            // The first tick completes immediately, but we've only just fetched the routers.
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                app_state.refresh_otp_routers().await;
+            }
+        });
+    }
+
+    pub(crate) fn update_otp_cluster(&self, update: impl FnOnce(&mut OtpCluster)) {
+        let mut otp_cluster = self.otp_cluster.write().expect("lock not poisoned");
+        let mut updated = OtpCluster::clone(&otp_cluster);
+        update(&mut updated);
+        *otp_cluster = Arc::new(updated);
+    }
+
+    /// A snapshot of the OTP cluster, unaffected by any subsequent refresh.
+    pub fn otp_cluster(&self) -> Arc<OtpCluster> {
+        self.otp_cluster.read().expect("lock not poisoned").clone()
+    }
+
+    pub fn otp_time_zone(&self, router_url: &Url) -> Option<chrono_tz::Tz> {
+        self.otp_time_zones
+            .read()
+            .expect("lock not poisoned")
+            .get(router_url)
+            .copied()
+    }
+
+    pub fn set_otp_time_zone(&self, router_url: Url, time_zone: chrono_tz::Tz) {
+        self.otp_time_zones
+            .write()
+            .expect("lock not poisoned")
+            .insert(router_url, time_zone);
     }
 
     pub fn valhalla_router(&self) -> &ValhallaRouter {
@@ -51,4 +252,16 @@ impl AppState {
     pub fn elevation(&self) -> &ElevationService {
         &self.elevation
     }
+
+    pub fn upstream_config(&self) -> &UpstreamConfig {
+        &self.upstream_config
+    }
+
+    pub fn readiness_config(&self) -> &ReadinessConfig {
+        &self.readiness_config
+    }
+
+    pub fn http_client(&self) -> &reqwest::Client {
+        &self.http_client
+    }
 }
@@ -1,15 +1,327 @@
 use actix_web::{get, web, HttpResponse, Responder};
+use futures_util::future::join_all;
+use serde::Serialize;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use url::Url;
 
 use crate::api::AppState;
+use crate::{Error, Result};
 
 #[get("/health/alive")]
 pub async fn get_alive(_app_state: web::Data<AppState>) -> impl Responder {
     HttpResponse::Ok()
 }
 
+/// Which dependencies need to be healthy for us to be ready.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadinessConfig {
+    /// If any dependency of these kinds is unhealthy, we're not ready. Unhealthy dependencies of
+    /// other kinds only leave us degraded.
+    pub required: Vec<DependencyKind>,
+    /// How long to wait for each dependency to respond to a probe.
+    pub probe_timeout: Duration,
+}
+
+impl Default for ReadinessConfig {
+    fn default() -> Self {
+        Self {
+            // Without valhalla we can't route anything, whereas without OTP or elevation data
+            // we can still serve most requests.
+            required: vec![DependencyKind::Valhalla],
+            probe_timeout: Duration::from_secs(2),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum DependencyKind {
+    Valhalla,
+    Otp,
+    Elevation,
+}
+
+impl FromStr for DependencyKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "valhalla" => Ok(Self::Valhalla),
+            "otp" => Ok(Self::Otp),
+            "elevation" => Ok(Self::Elevation),
+            other => Err(Error::server(format!("unknown dependency: `{other}`"))),
+        }
+    }
+}
+
+impl DependencyKind {
+    /// Parse a comma separated list, e.g. `valhalla,otp`
+    pub fn parse_list(s: &str) -> Result<Vec<Self>> {
+        s.split(',')
+            .filter(|kind| !kind.trim().is_empty())
+            .map(Self::from_str)
+            .collect()
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadyResponse {
+    status: ReadyStatus,
+    dependencies: Vec<DependencyHealth>,
+    otp_endpoints: OtpEndpointsStatus,
+}
+
+#[derive(Debug, Serialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum ReadyStatus {
+    Ready,
+    /// We can serve requests, but some dependencies are unhealthy, e.g. transit directions may
+    /// be unavailable in some areas.
+    Degraded,
+    /// A required dependency is unhealthy.
+    NotReady,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DependencyHealth {
+    kind: DependencyKind,
+    /// The url or path of the dependency
+    name: String,
+    healthy: bool,
+    latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct OtpEndpointsStatus {
+    loaded: Vec<String>,
+    pending: Vec<String>,
+}
+
+impl DependencyHealth {
+    fn new(
+        kind: DependencyKind,
+        name: String,
+        started_at: Instant,
+        result: std::result::Result<(), String>,
+    ) -> Self {
+        Self {
+            kind,
+            name,
+            healthy: result.is_ok(),
+            latency_ms: started_at.elapsed().as_millis() as u64,
+            error: result.err(),
+        }
+    }
+}
+
+impl ReadyResponse {
+    async fn probe(app_state: &AppState) -> Self {
+        let config = app_state.readiness_config();
+        let otp_cluster = app_state.otp_cluster();
+
+        let mut http_probes = vec![probe_url(
+            app_state,
+            DependencyKind::Valhalla,
+            app_state.valhalla_router().status_url(),
+            true,
+        )];
+        for endpoint in otp_cluster.endpoints() {
+            let loaded = otp_cluster
+                .loaded_endpoints()
+                .any(|loaded| loaded == endpoint);
+            http_probes.push(probe_url(
+                app_state,
+                DependencyKind::Otp,
+                endpoint.clone(),
+                loaded,
+            ));
+        }
+        let mut dependencies = join_all(http_probes).await;
+        dependencies.push(probe_elevation(app_state));
+
+        let otp_endpoints = OtpEndpointsStatus {
+            loaded: otp_cluster.loaded_endpoints().map(Url::to_string).collect(),
+            pending: otp_cluster
+                .pending_endpoints()
+                .map(Url::to_string)
+                .collect(),
+        };
+        Self {
+            status: Self::status(&dependencies, &config.required),
+            dependencies,
+            otp_endpoints,
+        }
+    }
+
+    fn status(dependencies: &[DependencyHealth], required: &[DependencyKind]) -> ReadyStatus {
+        let mut unhealthy = dependencies.iter().filter(|d| !d.healthy).peekable();
+        if unhealthy.peek().is_none() {
+            ReadyStatus::Ready
+        } else if unhealthy.any(|d| required.contains(&d.kind)) {
+            ReadyStatus::NotReady
+        } else {
+            ReadyStatus::Degraded
+        }
+    }
+}
+
+/// `loaded` is false for an OTP endpoint whose routers we haven't fetched yet - even if it's
+/// responding now, we can't route with it until the next retry.
+async fn probe_url(
+    app_state: &AppState,
+    kind: DependencyKind,
+    url: Url,
+    loaded: bool,
+) -> DependencyHealth {
+    let started_at = Instant::now();
+    let response = app_state
+        .http_client()
+        .get(url.clone())
+        .timeout(app_state.readiness_config().probe_timeout)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status);
+    let result = match response {
+        Err(err) => Err(err.to_string()),
+        Ok(_) if !loaded => Err("routers not loaded yet".to_string()),
+        Ok(_) => Ok(()),
+    };
+    DependencyHealth::new(kind, url.to_string(), started_at, result)
+}
+
+fn probe_elevation(app_state: &AppState) -> DependencyHealth {
+    let started_at = Instant::now();
+    let elevation = app_state.elevation();
+    let result = match elevation.tif_count() {
+        Err(err) => Err(err.to_string()),
+        Ok(0) => Err("no elevation tifs".to_string()),
+        Ok(_) => Ok(()),
+    };
+    DependencyHealth::new(
+        DependencyKind::Elevation,
+        elevation.tif_dir().display().to_string(),
+        started_at,
+        result,
+    )
+}
+
 #[get("/health/ready")]
-pub async fn get_ready(_app_state: web::Data<AppState>) -> impl Responder {
-    // Currently all setup happens before the server is listening
-    // So if we are handling requests, we are ready!
-    HttpResponse::Ok()
+pub async fn get_ready(app_state: web::Data<AppState>) -> impl Responder {
+    let response = ReadyResponse::probe(&app_state).await;
+    match response.status {
+        ReadyStatus::Ready | ReadyStatus::Degraded => HttpResponse::Ok().json(response),
+        ReadyStatus::NotReady => HttpResponse::ServiceUnavailable().json(response),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn app_state() -> AppState {
+        // Nothing is listening on this port
+        AppState::new(
+            Url::parse("http://127.0.0.1:1").unwrap(),
+            PathBuf::from("tests/fixtures/low_res_elevation_tifs"),
+        )
+    }
+
+    fn dependency(kind: DependencyKind, healthy: bool) -> DependencyHealth {
+        DependencyHealth {
+            kind,
+            name: "test".to_string(),
+            healthy,
+            latency_ms: 0,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn parse_dependency_kinds() {
+        assert_eq!(
+            DependencyKind::parse_list("valhalla, OTP").unwrap(),
+            vec![DependencyKind::Valhalla, DependencyKind::Otp]
+        );
+        assert_eq!(DependencyKind::parse_list("").unwrap(), vec![]);
+        assert!(DependencyKind::parse_list("valhalla,osrm").is_err());
+    }
+
+    #[test]
+    fn status_from_dependencies() {
+        use DependencyKind::*;
+        let required = [Valhalla];
+
+        let healthy = [dependency(Valhalla, true), dependency(Otp, true)];
+        assert_eq!(
+            ReadyResponse::status(&healthy, &required),
+            ReadyStatus::Ready
+        );
+
+        let otp_down = [dependency(Valhalla, true), dependency(Otp, false)];
+        assert_eq!(
+            ReadyResponse::status(&otp_down, &required),
+            ReadyStatus::Degraded
+        );
+        assert_eq!(
+            ReadyResponse::status(&otp_down, &[Valhalla, Otp]),
+            ReadyStatus::NotReady
+        );
+
+        let valhalla_down = [dependency(Valhalla, false), dependency(Otp, true)];
+        assert_eq!(
+            ReadyResponse::status(&valhalla_down, &required),
+            ReadyStatus::NotReady
+        );
+        assert_eq!(
+            ReadyResponse::status(&valhalla_down, &[]),
+            ReadyStatus::Degraded
+        );
+    }
+
+    #[actix_web::test]
+    async fn probe_dependencies() {
+        let app_state = app_state();
+
+        let endpoint = "http://127.0.0.1:1/otp/routers";
+        assert!(app_state.add_otp_endpoint(endpoint).await.is_err());
+
+        let response = ReadyResponse::probe(&app_state).await;
+        assert_eq!(response.status, ReadyStatus::NotReady);
+        assert!(response.otp_endpoints.loaded.is_empty());
+        assert_eq!(response.otp_endpoints.pending, vec![endpoint.to_string()]);
+
+        let kinds_and_health: Vec<_> = response
+            .dependencies
+            .iter()
+            .map(|d| (d.kind, d.healthy))
+            .collect();
+        assert_eq!(
+            kinds_and_health,
+            vec![
+                (DependencyKind::Valhalla, false),
+                (DependencyKind::Otp, false),
+                (DependencyKind::Elevation, true),
+            ]
+        );
+        assert_eq!(response.dependencies[0].name, "http://127.0.0.1:1/status");
+        assert!(response.dependencies[0].error.is_some());
+    }
+
+    #[actix_web::test]
+    async fn missing_elevation_tifs() {
+        let app_state = AppState::new(
+            Url::parse("http://127.0.0.1:1").unwrap(),
+            PathBuf::from("src"),
+        );
+        let elevation = probe_elevation(&app_state);
+        assert!(!elevation.healthy);
+        assert_eq!(elevation.error.as_deref(), Some("no elevation tifs"));
+    }
 }
@@ -4,6 +4,7 @@ use crate::error::ErrorType;
 use crate::otp::otp_api;
 use crate::otp::otp_api::{AbsoluteDirection, RelativeDirection};
 use crate::util::format::format_meters;
+use crate::util::i18n::Language;
 use crate::util::serde_util::{
     deserialize_point_from_lat_lon, serialize_rect_to_lng_lat, serialize_system_time_as_millis,
 };
@@ -94,8 +95,8 @@ impl Itinerary {
                     mode,
                     leg_start_time,
                     leg_end_time,
-                    locations[0],
-                    locations[1],
+                    (&locations[0]).into(),
+                    (&locations[1]).into(),
                 )
             })
             .collect();
@@ -416,7 +417,11 @@ fn build_verbal_post_transition_instruction(
     } else {
         Some(format!(
             "Continue for {}.",
-            format_meters(distance, distance_unit.measurement_system())
+            format_meters(
+                distance,
+                distance_unit.measurement_system(),
+                Language::English
+            )
         ))
     }
 }
@@ -591,16 +596,21 @@ pub async fn get_plan(
                 TravelMode::Walk => valhalla_api::ModeCosting::Pedestrian,
             };
 
-            // route?json={%22locations%22:[{%22lat%22:47.575837,%22lon%22:-122.339414},{%22lat%22:47.651048,%22lon%22:-122.347234}],%22costing%22:%22auto%22,%22alternates%22:3,%22units%22:%22miles%22}
-            let router_url = app_state.valhalla_router().plan_url(
-                query.from_place,
-                query.to_place,
+            let route_query = app_state.valhalla_router().route_query(
+                &[query.from_place, query.to_place],
                 mode,
                 query.num_itineraries,
                 distance_units,
-            )?;
-            let valhalla_response: reqwest::Response =
-                reqwest::get(router_url).await.map_err(|e| {
+                None,
+            );
+            let valhalla_response: reqwest::Response = app_state
+                .http_client()
+                .post(app_state.valhalla_router().route_url())
+                .json(&route_query)
+                .timeout(app_state.upstream_config().valhalla_timeout)
+                .send()
+                .await
+                .map_err(|e| {
                     log::error!("error while fetching from valhalla service: {e}");
                     PlanResponseErr::from(Error::server(e))
                 })?;
@@ -691,10 +701,16 @@ async fn otp_plan(
     router_url.set_query(Some(req.query_string()));
     log::debug!("found matching router. Forwarding request to: {router_url}",);
 
-    let otp_response: reqwest::Response = reqwest::get(router_url).await.map_err(|e| {
-        log::error!("error while fetching from otp service: {e}");
-        PlanResponseErr::from(Error::server(e))
-    })?;
+    let otp_response: reqwest::Response = app_state
+        .http_client()
+        .get(router_url)
+        .timeout(app_state.upstream_config().otp_timeout)
+        .send()
+        .await
+        .map_err(|e| {
+            log::error!("error while fetching from otp service: {e}");
+            PlanResponseErr::from(Error::server(e))
+        })?;
     if !otp_response.status().is_success() {
         log::warn!(
             "upstream HTTP Error from otp service: {}",
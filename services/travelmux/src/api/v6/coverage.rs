@@ -0,0 +1,176 @@
+use crate::api::AppState;
+use crate::otp::OtpCluster;
+use crate::valhalla::valhalla_api;
+use crate::Result;
+use actix_web::{get, web, HttpResponse, Responder};
+use geojson::{Feature, FeatureCollection, GeoJson, Geometry, JsonObject};
+
+/// Where we can plan trips: a GeoJSON FeatureCollection with a feature for each OTP router's
+/// polygon, and for the extent of Valhalla's graph.
+#[get("/v6/coverage")]
+pub async fn get_coverage(app_state: web::Data<AppState>) -> impl Responder {
+    let mut features = otp_coverage(&app_state.otp_cluster());
+    match valhalla_coverage(&app_state).await {
+        Ok(valhalla_features) => features.extend(valhalla_features),
+        // Transit coverage is still useful on its own
+        Err(err) => log::warn!("unable to fetch valhalla coverage: {err}"),
+    }
+
+    let feature_collection = FeatureCollection {
+        bbox: None,
+        features,
+        foreign_members: None,
+    };
+    HttpResponse::Ok()
+        .content_type("application/geo+json")
+        .body(feature_collection.to_string())
+}
+
+fn otp_coverage(otp_cluster: &OtpCluster) -> Vec<Feature> {
+    otp_cluster
+        .routers()
+        .map(|router| {
+            let mut properties = JsonObject::new();
+            properties.insert("source".to_string(), "otp".into());
+            properties.insert("routerId".to_string(), router.router_id().into());
+            properties.insert(
+                "endpointHost".to_string(),
+                router.endpoint().host_str().unwrap_or_default().into(),
+            );
+            Feature {
+                geometry: Some(Geometry::from(router.polygon())),
+                properties: Some(properties),
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+async fn valhalla_coverage(app_state: &AppState) -> Result<Vec<Feature>> {
+    let status: valhalla_api::StatusResponse = app_state
+        .http_client()
+        .get(app_state.valhalla_router().verbose_status_url())
+        .timeout(app_state.upstream_config().valhalla_timeout)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(status
+        .bbox
+        .map(valhalla_extent_features)
+        .unwrap_or_default())
+}
+
+fn valhalla_extent_features(bbox: GeoJson) -> Vec<Feature> {
+    let geometries = match bbox {
+        GeoJson::Geometry(geometry) => vec![geometry],
+        GeoJson::Feature(feature) => feature.geometry.into_iter().collect(),
+        GeoJson::FeatureCollection(feature_collection) => feature_collection
+            .features
+            .into_iter()
+            .filter_map(|feature| feature.geometry)
+            .collect(),
+    };
+    geometries
+        .into_iter()
+        .map(|geometry| {
+            let mut properties = JsonObject::new();
+            properties.insert("source".to_string(), "valhalla".into());
+            Feature {
+                geometry: Some(geometry),
+                properties: Some(properties),
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::otp::OTPRouter;
+    use actix_web::App;
+    use geo::Polygon;
+    use serde_json::{json, Value};
+    use std::path::PathBuf;
+    use url::Url;
+    use wkt::TryFromWkt;
+
+    #[test]
+    fn parse_valhalla_extent() {
+        let status: valhalla_api::StatusResponse = serde_json::from_value(json!({
+            "version": "3.4.0",
+            "tileset_last_modified": 1700000000,
+            "bbox": {
+                "type": "FeatureCollection",
+                "features": [{
+                    "type": "Feature",
+                    "properties": {},
+                    "geometry": {
+                        "type": "Polygon",
+                        "coordinates": [[[-123, 47], [-122, 47], [-122, 48], [-123, 48], [-123, 47]]]
+                    }
+                }]
+            }
+        }))
+        .unwrap();
+
+        let features = valhalla_extent_features(status.bbox.unwrap());
+        assert_eq!(features.len(), 1);
+        assert_eq!(
+            features[0].property("source"),
+            Some(&Value::from("valhalla"))
+        );
+        assert_eq!(
+            features[0].geometry.as_ref().unwrap().value.type_name(),
+            "Polygon"
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_get_coverage() {
+        // Nothing is listening on this port, so there's no valhalla coverage
+        let app_state = AppState::new(
+            Url::parse("http://127.0.0.1:1").unwrap(),
+            PathBuf::from("tests/fixtures/low_res_elevation_tifs"),
+        );
+        let endpoint = Url::parse("http://otp.example.com:9001/otp/routers").unwrap();
+        app_state.update_otp_cluster(|cluster| {
+            cluster.set_endpoint_routers(
+                endpoint.clone(),
+                vec![OTPRouter::new(
+                    endpoint.clone(),
+                    "metro".to_string(),
+                    Polygon::try_from_wkt_str("POLYGON ((0 0, 1 0, 1 1, 0 1, 0 0))").unwrap(),
+                )],
+            )
+        });
+
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(web::Data::new(app_state))
+                .service(get_coverage),
+        )
+        .await;
+        let req = actix_web::test::TestRequest::get()
+            .uri("/v6/coverage")
+            .to_request();
+        let resp = actix_web::test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        assert_eq!(
+            resp.headers().get("content-type").unwrap(),
+            "application/geo+json"
+        );
+
+        let body: Value = actix_web::test::read_body_json(resp).await;
+        assert_eq!(body["type"], "FeatureCollection");
+        let features = body["features"].as_array().unwrap();
+        assert_eq!(features.len(), 1);
+        assert_eq!(
+            features[0]["properties"],
+            json!({ "source": "otp", "routerId": "metro", "endpointHost": "otp.example.com" })
+        );
+        assert_eq!(features[0]["geometry"]["type"], "Polygon");
+    }
+}
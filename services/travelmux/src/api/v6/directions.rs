@@ -9,10 +9,10 @@ use serde::Serialize;
 #[get("/v6/directions")]
 pub async fn get_directions(
     query: web::Query<PlanQuery>,
-    req: HttpRequest,
+    _req: HttpRequest,
     app_state: web::Data<AppState>,
 ) -> Result<DirectionsResponseOk, PlanResponseErr> {
-    let plan_response_ok = _get_plan(query, req, app_state).await?;
+    let plan_response_ok = _get_plan(query, app_state).await?;
     Ok(plan_response_ok.into())
 }
 
@@ -50,6 +50,7 @@ mod tests {
     use super::PlanResponseOk;
     use super::*;
     use crate::otp::otp_api;
+    use crate::util::i18n::Language;
     use crate::valhalla::valhalla_api;
     use crate::{DistanceUnit, TravelMode};
     use approx::assert_relative_eq;
@@ -64,8 +65,13 @@ mod tests {
         let otp: otp_api::PlanResponse =
             serde_json::from_reader(BufReader::new(stubbed_response)).unwrap();
 
-        let plan_response =
-            PlanResponseOk::from_otp(TravelMode::Walk, otp, DistanceUnit::Miles).unwrap();
+        let plan_response = PlanResponseOk::from_otp(
+            TravelMode::Walk,
+            otp,
+            DistanceUnit::Miles,
+            Language::English,
+        )
+        .unwrap();
 
         let directions_response = DirectionsResponseOk::from(plan_response);
         assert_eq!(directions_response.routes.len(), 1);
@@ -136,7 +142,8 @@ mod tests {
 
         let valhalla_response_result = valhalla_api::ValhallaRouteResponseResult::Ok(valhalla);
         let plan_response =
-            PlanResponseOk::from_valhalla(TravelMode::Walk, valhalla_response_result).unwrap();
+            PlanResponseOk::from_valhalla(TravelMode::Walk, valhalla_response_result, None)
+                .unwrap();
 
         let directions_response = DirectionsResponseOk::from(plan_response);
         assert_eq!(directions_response.routes.len(), 3);
@@ -1,9 +1,14 @@
-use crate::otp::otp_api;
+use crate::otp::{otp_api, OtpCluster};
+use crate::util::i18n::Language;
+use crate::util::serde_util::serialize_rect_to_lng_lat;
 use crate::valhalla::valhalla_api;
 use crate::{DistanceUnit, Error, TravelMode};
 use actix_web::HttpResponseBuilder;
+use geo::{BoundingRect, Point, Polygon, Rect};
 use serde::Serialize;
 
+use super::locate::EndpointSnaps;
+use super::plan::RequestedTime;
 use super::{Itinerary, Plan};
 use crate::error::ErrorType;
 use actix_web::body::BoxBody;
@@ -24,12 +29,65 @@ pub struct UnboxedPlanResponseErr {
 }
 pub type PlanResponseErr = Box<UnboxedPlanResponseErr>;
 
+impl UnboxedPlanResponseErr {
+    /// The backend couldn't find a trip, as opposed to e.g. rejecting the request.
+    pub fn is_no_path(&self) -> bool {
+        if let Some(otp) = &self._otp {
+            otp.is_no_path()
+        } else if let Some(valhalla) = &self._valhalla {
+            valhalla.is_no_path()
+        } else {
+            false
+        }
+    }
+}
+
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct PlanError {
     pub status_code: u16,
     pub error_code: u32,
     pub message: String,
+    /// For `NoCoverageForArea` errors, where the nearest transit coverage is
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coverage: Option<CoverageHint>,
+    /// For errors finding a path, where the trip's endpoints were snapped onto the street
+    /// network, when requested with `reportSnapping`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snapping: Option<EndpointSnaps>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CoverageHint {
+    router_id: String,
+    /// The area covered by the router
+    #[serde(serialize_with = "geojson::ser::serialize_geometry")]
+    polygon: Polygon,
+    #[serde(serialize_with = "serialize_rect_to_lng_lat")]
+    bounds: Rect,
+    /// How far the trip's origin is from the covered area, 0 if it's within it
+    from_place_distance_meters: f64,
+    /// How far the trip's destination is from the covered area, 0 if it's within it
+    to_place_distance_meters: f64,
+}
+
+impl CoverageHint {
+    /// Describe the router nearest to a trip from `from_place` to `to_place`.
+    pub fn nearest(
+        otp_cluster: &OtpCluster,
+        from_place: Point,
+        to_place: Point,
+    ) -> Option<CoverageHint> {
+        let (router, distances) = otp_cluster.find_nearest_router(&[from_place, to_place])?;
+        Some(CoverageHint {
+            router_id: router.router_id().to_string(),
+            polygon: router.polygon().clone(),
+            bounds: router.polygon().bounding_rect()?,
+            from_place_distance_meters: distances[0],
+            to_place_distance_meters: distances[1],
+        })
+    }
 }
 
 #[derive(Debug, Serialize, Clone, PartialEq)]
@@ -72,6 +130,8 @@ impl From<&valhalla_api::RouteResponseError> for PlanError {
             status_code: value.status_code,
             error_code: value.error_code + 2000,
             message: value.error.clone(),
+            coverage: None,
+            snapping: None,
         }
     }
 }
@@ -106,16 +166,22 @@ impl From<Error> for PlanError {
                 status_code: 400,
                 error_code,
                 message: value.source.to_string(),
+                coverage: None,
+                snapping: None,
             },
             ErrorType::User => Self {
                 status_code: 400,
                 error_code,
                 message: value.source.to_string(),
+                coverage: None,
+                snapping: None,
             },
             ErrorType::Server => Self {
                 status_code: 500,
                 error_code,
                 message: value.source.to_string(),
+                coverage: None,
+                snapping: None,
             },
         }
     }
@@ -129,6 +195,8 @@ impl From<&otp_api::PlanError> for PlanError {
             status_code: 400,
             error_code: value.id,
             message: value.msg.clone(),
+            coverage: None,
+            snapping: None,
         }
     }
 }
@@ -156,6 +224,7 @@ impl PlanResponseOk {
         mode: TravelMode,
         mut otp: otp_api::PlanResponse,
         distance_unit: DistanceUnit,
+        language: Language,
     ) -> Result<PlanResponseOk, PlanResponseErr> {
         if let Some(otp_error) = otp.error {
             return Err(otp_error.into());
@@ -168,7 +237,7 @@ impl PlanResponseOk {
             .itineraries
             .iter()
             .map(|itinerary: &otp_api::Itinerary| {
-                Itinerary::from_otp(itinerary, mode, distance_unit)
+                Itinerary::from_otp(itinerary, mode, distance_unit, language)
             })
             .collect();
 
@@ -181,19 +250,73 @@ impl PlanResponseOk {
         })
     }
 
+    /// Combine the itineraries planned by OTP and Valhalla for the same trip, dropping any
+    /// Valhalla itineraries that duplicate an OTP itinerary, and keeping the `limit` fastest.
+    pub fn merge(otp: PlanResponseOk, valhalla: PlanResponseOk, limit: usize) -> PlanResponseOk {
+        let mut itineraries = otp.plan.itineraries;
+        for valhalla_itinerary in valhalla.plan.itineraries {
+            // Prefer OTP's version of any duplicate - anecdotally it tends to be higher quality
+            if itineraries
+                .iter()
+                .any(|itinerary| itinerary.is_similar_to(&valhalla_itinerary))
+            {
+                continue;
+            }
+            itineraries.push(valhalla_itinerary);
+        }
+        itineraries.sort_by(|a, b| {
+            a.duration
+                .total_cmp(&b.duration)
+                .then(a.distance_meters().total_cmp(&b.distance_meters()))
+        });
+        itineraries.truncate(limit);
+
+        PlanResponseOk {
+            plan: Plan { itineraries },
+            _otp: otp._otp,
+            _valhalla: valhalla._valhalla,
+        }
+    }
+
+    /// Continue each of OTP's itineraries with Valhalla's, for a trip which OTP could only plan
+    /// part of.
+    pub fn stitch(otp: PlanResponseOk, connection: PlanResponseOk) -> PlanResponseOk {
+        let mut itineraries = otp.plan.itineraries;
+        if let Some(connection_itinerary) = connection.plan.itineraries.first() {
+            for itinerary in &mut itineraries {
+                itinerary.stitch(connection_itinerary);
+            }
+        }
+
+        PlanResponseOk {
+            plan: Plan { itineraries },
+            _otp: otp._otp,
+            _valhalla: connection._valhalla,
+        }
+    }
+
     pub fn from_valhalla(
         mode: TravelMode,
         valhalla: valhalla_api::ValhallaRouteResponseResult,
+        requested_time: Option<RequestedTime>,
     ) -> Result<PlanResponseOk, valhalla_api::RouteResponseError> {
         let valhalla = match valhalla {
             valhalla_api::ValhallaRouteResponseResult::Ok(valhalla) => valhalla,
             valhalla_api::ValhallaRouteResponseResult::Err(err) => return Err(err),
         };
 
-        let mut itineraries = vec![Itinerary::from_valhalla(&valhalla.trip, mode)];
+        let mut itineraries = vec![Itinerary::from_valhalla(
+            &valhalla.trip,
+            mode,
+            requested_time,
+        )];
         if let Some(alternates) = &valhalla.alternates {
             for alternate in alternates {
-                itineraries.push(Itinerary::from_valhalla(&alternate.trip, mode));
+                itineraries.push(Itinerary::from_valhalla(
+                    &alternate.trip,
+                    mode,
+                    requested_time,
+                ));
             }
         }
 
@@ -204,3 +327,45 @@ impl PlanResponseOk {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::otp::OTPRouter;
+    use serde_json::json;
+    use wkt::TryFromWkt;
+
+    #[test]
+    fn serialize_coverage_hint() {
+        let mut otp_cluster = OtpCluster::default();
+        otp_cluster.push_router(OTPRouter::new(
+            url::Url::parse("http://host.example.com/otp/routers").unwrap(),
+            "metro".to_string(),
+            Polygon::try_from_wkt_str("POLYGON ((0 0, 1 0, 1 1, 0 1, 0 0))").unwrap(),
+        ));
+
+        let hint = CoverageHint::nearest(&otp_cluster, Point::new(0.5, 0.5), Point::new(1.0, 2.0))
+            .unwrap();
+        let mut error = PlanResponseErr::from(
+            Error::user("Transit directions not available for this area.")
+                .error_type(ErrorType::NoCoverageForArea),
+        );
+        error.error.coverage = Some(hint);
+
+        let json = serde_json::to_value(&error).unwrap();
+        let coverage = &json["error"]["coverage"];
+        assert_eq!(coverage["routerId"], "metro");
+        assert_eq!(coverage["polygon"]["type"], "Polygon");
+        assert_eq!(
+            coverage["bounds"],
+            json!({ "min": [0.0, 0.0], "max": [1.0, 1.0] })
+        );
+        assert_eq!(coverage["fromPlaceDistanceMeters"], 0.0);
+        let to_place_distance = coverage["toPlaceDistanceMeters"].as_f64().unwrap();
+        assert!((111_000.0..111_400.0).contains(&to_place_distance));
+
+        // Other errors have no coverage hint
+        let json = serde_json::to_value(PlanResponseErr::from(Error::user("bad input"))).unwrap();
+        assert!(json["error"].get("coverage").is_none());
+    }
+}
@@ -0,0 +1,349 @@
+use super::error::PlanResponseErr;
+use crate::api::AppState;
+use crate::error::ErrorType;
+use crate::otp::{OtpApi, OtpPlanEndpoint};
+use crate::util::serde_util::deserialize_point_from_lat_lon;
+use crate::valhalla::valhalla_api;
+use crate::valhalla::valhalla_api::LonLat;
+use crate::{Error, Result, TravelMode};
+use actix_web::{get, web, HttpResponse};
+use geo::Point;
+use geojson::{Feature, FeatureCollection, JsonObject};
+use serde::Deserialize;
+
+/// Valhalla's default limits
+const MAX_CONTOURS: usize = 4;
+const MAX_CONTOUR_MINUTES: u32 = 120;
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct IsochroneQuery {
+    #[serde(deserialize_with = "deserialize_point_from_lat_lon")]
+    location: Point,
+
+    mode: TravelMode,
+
+    /// Minutes of travel, formatted as `10,20,30`
+    contours: String,
+}
+
+impl IsochroneQuery {
+    /// The requested contours in minutes, from shortest to longest.
+    fn contour_minutes(&self) -> Result<Vec<u32>> {
+        let mut minutes = self
+            .contours
+            .split(',')
+            .map(|contour| {
+                let minutes: u32 = contour.trim().parse().map_err(|_| {
+                    Error::user(format!(
+                        "contours must be a list of whole minutes, got: {contour:?}"
+                    ))
+                })?;
+                if !(1..=MAX_CONTOUR_MINUTES).contains(&minutes) {
+                    return Err(Error::user(format!(
+                        "contours must be between 1 and {MAX_CONTOUR_MINUTES} minutes, got: {minutes}"
+                    )));
+                }
+                Ok(minutes)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if minutes.len() > MAX_CONTOURS {
+            return Err(Error::user(format!(
+                "at most {MAX_CONTOURS} contours are supported, got: {}",
+                minutes.len()
+            )));
+        }
+        minutes.sort();
+        minutes.dedup();
+        Ok(minutes)
+    }
+}
+
+/// The areas reachable from `location` within each of the `contours`, as a GeoJSON
+/// FeatureCollection with a polygon feature per contour, from shortest to longest.
+///
+/// Each feature's properties include its `contourMinutes`, and the `source` which computed it:
+/// "valhalla" for walking, cycling and driving, or "otp" for transit.
+#[get("/v6/isochrone")]
+pub async fn get_isochrone(
+    query: web::Query<IsochroneQuery>,
+    app_state: web::Data<AppState>,
+) -> std::result::Result<HttpResponse, PlanResponseErr> {
+    let contour_minutes = query.contour_minutes()?;
+    let features = match query.mode {
+        TravelMode::Transit => otp_isochrone(&query, &contour_minutes, &app_state).await?,
+        TravelMode::Walk | TravelMode::Bicycle | TravelMode::Car => {
+            valhalla_isochrone(&query, &contour_minutes, &app_state).await?
+        }
+    };
+
+    let feature_collection = FeatureCollection {
+        bbox: None,
+        features,
+        foreign_members: None,
+    };
+    Ok(HttpResponse::Ok()
+        .content_type("application/geo+json")
+        .body(feature_collection.to_string()))
+}
+
+async fn valhalla_isochrone(
+    query: &IsochroneQuery,
+    contour_minutes: &[u32],
+    app_state: &AppState,
+) -> std::result::Result<Vec<Feature>, PlanResponseErr> {
+    let costing = match query.mode {
+        TravelMode::Walk => valhalla_api::ModeCosting::Pedestrian,
+        TravelMode::Bicycle => valhalla_api::ModeCosting::Bicycle,
+        TravelMode::Car => valhalla_api::ModeCosting::Auto,
+        TravelMode::Transit => unreachable!("transit isochrones come from OTP"),
+    };
+    let isochrone_query = valhalla_api::IsochroneQuery {
+        locations: vec![LonLat::from(query.location)],
+        costing,
+        contours: contour_minutes
+            .iter()
+            .map(|&minutes| valhalla_api::Contour {
+                time: minutes as f64,
+            })
+            .collect(),
+        polygons: true,
+    };
+
+    let response: valhalla_api::IsochroneResponseResult = app_state
+        .http_client()
+        .post(app_state.valhalla_router().isochrone_url())
+        .json(&isochrone_query)
+        .timeout(app_state.upstream_config().valhalla_timeout)
+        .send()
+        .await
+        .map_err(|e| {
+            log::error!("error while fetching isochrone from valhalla service: {e}");
+            PlanResponseErr::from(Error::server(e))
+        })?
+        .json()
+        .await
+        .map_err(|e| {
+            log::error!("error while parsing valhalla isochrone response: {e}");
+            PlanResponseErr::from(Error::server(e))
+        })?;
+
+    match response {
+        valhalla_api::IsochroneResponseResult::Ok(feature_collection) => Ok(contour_features(
+            feature_collection,
+            "valhalla",
+            |properties| properties.get("contour")?.as_f64(),
+        )),
+        valhalla_api::IsochroneResponseResult::Err(err) => Err(err.into()),
+    }
+}
+
+async fn otp_isochrone(
+    query: &IsochroneQuery,
+    contour_minutes: &[u32],
+    app_state: &AppState,
+) -> Result<Vec<Feature>> {
+    let Some(endpoint) = app_state
+        .otp_cluster()
+        .find_isochrone_endpoint(query.location)
+    else {
+        return Err(
+            Error::user("Transit directions not available for this area.")
+                .error_type(ErrorType::NoCoverageForArea),
+        );
+    };
+
+    let isochrone_url = otp_isochrone_url(&endpoint, query.location, contour_minutes);
+    log::debug!("found matching router. Sending isochrone request to: {isochrone_url}");
+    let response = app_state
+        .http_client()
+        .get(isochrone_url)
+        .timeout(app_state.upstream_config().otp_timeout)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        log::warn!(
+            "upstream HTTP Error from otp isochrone: {}",
+            response.status()
+        );
+        return Err(Error::server(format!(
+            "transit isochrone failed with status {}",
+            response.status()
+        )));
+    }
+    let feature_collection: FeatureCollection = response.json().await?;
+
+    // OTP reports each contour's travel time in seconds
+    Ok(contour_features(feature_collection, "otp", |properties| {
+        Some(properties.get("time")?.as_f64()? / 60.0)
+    }))
+}
+
+fn otp_isochrone_url(
+    endpoint: &OtpPlanEndpoint,
+    location: Point,
+    contour_minutes: &[u32],
+) -> url::Url {
+    let mut url = endpoint.url.clone();
+    let lat_lon = format!("{},{}", location.y(), location.x());
+    {
+        let mut query_pairs = url.query_pairs_mut();
+        match endpoint.api {
+            OtpApi::Rest => {
+                query_pairs
+                    .append_pair("fromPlace", &lat_lon)
+                    .append_pair("mode", "WALK,TRANSIT");
+                for minutes in contour_minutes {
+                    query_pairs.append_pair("cutoffSec", &(minutes * 60).to_string());
+                }
+            }
+            OtpApi::GraphQl => {
+                let now = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+                query_pairs
+                    .append_pair("location", &lat_lon)
+                    .append_pair("time", &now)
+                    .append_pair("modes", "WALK,TRANSIT");
+                for minutes in contour_minutes {
+                    query_pairs.append_pair("cutoff", &format!("{minutes}m"));
+                }
+            }
+        }
+    }
+    url
+}
+
+/// Normalize a backend's isochrone features, using `contour_minutes` to read the minutes
+/// from each feature's properties.
+fn contour_features(
+    feature_collection: FeatureCollection,
+    source: &str,
+    contour_minutes: impl Fn(&JsonObject) -> Option<f64>,
+) -> Vec<Feature> {
+    let mut features: Vec<(f64, Feature)> = feature_collection
+        .features
+        .into_iter()
+        .filter_map(|feature| {
+            let Some(minutes) = feature.properties.as_ref().and_then(&contour_minutes) else {
+                log::warn!("ignoring {source} isochrone feature without a contour");
+                return None;
+            };
+            let mut properties = JsonObject::new();
+            properties.insert("contourMinutes".to_string(), minutes.round().into());
+            properties.insert("source".to_string(), source.into());
+            Some((
+                minutes,
+                Feature {
+                    geometry: feature.geometry,
+                    properties: Some(properties),
+                    ..Default::default()
+                },
+            ))
+        })
+        .collect();
+    features.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+    features.into_iter().map(|(_, feature)| feature).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::web::Query;
+    use serde_json::json;
+
+    #[test]
+    fn parse_contours() {
+        let parse = |contours: &str| {
+            Query::<IsochroneQuery>::from_query(&format!(
+                "location=47.6,-122.3&mode=WALK&contours={contours}"
+            ))
+            .unwrap()
+            .contour_minutes()
+        };
+        assert_eq!(parse("20,10,20").unwrap(), vec![10, 20]);
+        assert_eq!(parse("5").unwrap(), vec![5]);
+
+        for bad in ["", "ten", "0", "121", "1,2,3,4,5"] {
+            let err = parse(bad).unwrap_err();
+            assert_eq!(err.error_type, ErrorType::User, "{bad}");
+        }
+    }
+
+    #[test]
+    fn normalize_valhalla_features() {
+        let valhalla: valhalla_api::IsochroneResponseResult = serde_json::from_value(json!({
+            "type": "FeatureCollection",
+            "features": [
+                {
+                    "type": "Feature",
+                    "properties": { "contour": 20, "metric": "time", "fill": "#bf4040" },
+                    "geometry": { "type": "Polygon", "coordinates": [[[0, 0], [2, 0], [2, 2], [0, 0]]] }
+                },
+                {
+                    "type": "Feature",
+                    "properties": { "contour": 10, "metric": "time", "fill": "#40bf40" },
+                    "geometry": { "type": "Polygon", "coordinates": [[[0, 0], [1, 0], [1, 1], [0, 0]]] }
+                }
+            ]
+        }))
+        .unwrap();
+        let valhalla_api::IsochroneResponseResult::Ok(feature_collection) = valhalla else {
+            panic!("expected isochrones");
+        };
+        let features = contour_features(feature_collection, "valhalla", |properties| {
+            properties.get("contour")?.as_f64()
+        });
+        let properties: Vec<_> = features
+            .iter()
+            .map(|feature| serde_json::to_value(&feature.properties).unwrap())
+            .collect();
+        assert_eq!(
+            properties,
+            vec![
+                json!({ "contourMinutes": 10.0, "source": "valhalla" }),
+                json!({ "contourMinutes": 20.0, "source": "valhalla" }),
+            ]
+        );
+
+        let error: valhalla_api::IsochroneResponseResult = serde_json::from_value(json!({
+            "error_code": 171,
+            "error": "No suitable edges near location",
+            "status_code": 400,
+            "status": "Bad Request"
+        }))
+        .unwrap();
+        assert!(matches!(
+            error,
+            valhalla_api::IsochroneResponseResult::Err(_)
+        ));
+    }
+
+    #[test]
+    fn otp_isochrone_request() {
+        let endpoint = OtpPlanEndpoint {
+            url: url::Url::parse("http://otp.example.com/otp/routers/default/isochrone").unwrap(),
+            api: OtpApi::Rest,
+        };
+        let url = otp_isochrone_url(&endpoint, Point::new(-122.3, 47.6), &[10, 20]);
+        assert_eq!(
+            url.as_str(),
+            "http://otp.example.com/otp/routers/default/isochrone?fromPlace=47.6%2C-122.3&mode=WALK%2CTRANSIT&cutoffSec=600&cutoffSec=1200"
+        );
+
+        let feature_collection: FeatureCollection = serde_json::from_value(json!({
+            "type": "FeatureCollection",
+            "features": [{
+                "type": "Feature",
+                "properties": { "time": 600 },
+                "geometry": { "type": "MultiPolygon", "coordinates": [[[[0, 0], [1, 0], [1, 1], [0, 0]]]] }
+            }]
+        }))
+        .unwrap();
+        let features = contour_features(feature_collection, "otp", |properties| {
+            Some(properties.get("time")?.as_f64()? / 60.0)
+        });
+        assert_eq!(
+            serde_json::to_value(&features[0].properties).unwrap(),
+            json!({ "contourMinutes": 10.0, "source": "otp" })
+        );
+    }
+}
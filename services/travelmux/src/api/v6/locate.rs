@@ -0,0 +1,240 @@
+use super::error::PlanResponseErr;
+use crate::api::AppState;
+use crate::util::serde_util::deserialize_points_from_lat_lon_list;
+use crate::valhalla::valhalla_api;
+use crate::valhalla::valhalla_api::LonLat;
+use crate::{Error, TravelMode};
+use actix_web::{get, web, HttpResponse};
+use geo::{Distance, Haversine, Point};
+use serde::{Deserialize, Serialize};
+
+/// Valhalla's default `max_locations` for `/locate`
+const MAX_LOCATIONS: usize = 50;
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LocateQuery {
+    /// Formatted as `lat,lon|lat,lon`
+    #[serde(deserialize_with = "deserialize_points_from_lat_lon_list")]
+    places: Vec<Point>,
+
+    mode: TravelMode,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LocateResponse {
+    /// In the same order as the requested `places`
+    locations: Vec<Location>,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Location {
+    requested: LonLat,
+    /// `None` if there's nowhere nearby to travel from or to by the requested mode
+    snap: Option<Snap>,
+}
+
+/// Where a requested place gets snapped onto the street network
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Snap {
+    #[serde(flatten)]
+    location: LonLat,
+    /// How far the snapped location is from the requested place
+    distance_meters: f64,
+    /// The name of the street it's snapped onto, if it has one
+    street_name: Option<String>,
+}
+
+/// Where a trip's endpoints are snapped onto the street network, each `None` if there's nowhere
+/// nearby to snap to.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct EndpointSnaps {
+    pub from_place: Option<Snap>,
+    pub to_place: Option<Snap>,
+}
+
+impl Snap {
+    /// The nearest of the edges Valhalla located.
+    fn from_valhalla(located: &valhalla_api::Located) -> Option<Self> {
+        let requested = Point::new(located.input_lon, located.input_lat);
+        located
+            .edges
+            .as_ref()?
+            .iter()
+            .map(|edge| {
+                let snapped = Point::new(edge.correlated_lon, edge.correlated_lat);
+                Snap {
+                    location: snapped.into(),
+                    distance_meters: Haversine.distance(requested, snapped),
+                    street_name: edge
+                        .edge_info
+                        .as_ref()
+                        .and_then(|edge_info| edge_info.names.first().cloned()),
+                }
+            })
+            .min_by(|a, b| a.distance_meters.total_cmp(&b.distance_meters))
+    }
+}
+
+/// Where each of `places` would be snapped onto the street network when planning a trip with
+/// `mode`, to explain why a dropped pin can't be routed to, or routes somewhere unexpected.
+#[get("/v6/locate")]
+pub async fn get_locate(
+    query: web::Query<LocateQuery>,
+    app_state: web::Data<AppState>,
+) -> std::result::Result<HttpResponse, PlanResponseErr> {
+    if query.places.is_empty() {
+        return Err(Error::user("places are required").into());
+    }
+    if query.places.len() > MAX_LOCATIONS {
+        return Err(Error::user(format!(
+            "at most {MAX_LOCATIONS} places are supported, got: {}",
+            query.places.len()
+        ))
+        .into());
+    }
+
+    let snaps = fetch_snaps(&app_state, &query.places, query.mode).await?;
+    let locations = query
+        .places
+        .iter()
+        .zip(snaps)
+        .map(|(&place, snap)| Location {
+            requested: place.into(),
+            snap,
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(LocateResponse { locations }))
+}
+
+/// Where each of `places` gets snapped onto the street network for `mode`.
+pub(super) async fn fetch_snaps(
+    app_state: &AppState,
+    places: &[Point],
+    mode: TravelMode,
+) -> std::result::Result<Vec<Option<Snap>>, PlanResponseErr> {
+    let costing = match mode {
+        // Transit trips start and end on foot
+        TravelMode::Walk | TravelMode::Transit => valhalla_api::ModeCosting::Pedestrian,
+        TravelMode::Bicycle => valhalla_api::ModeCosting::Bicycle,
+        TravelMode::Car => valhalla_api::ModeCosting::Auto,
+    };
+    let locate_query = valhalla_api::LocateQuery {
+        locations: places.iter().copied().map(LonLat::from).collect(),
+        costing,
+        verbose: true,
+    };
+
+    let response: valhalla_api::LocateResponseResult = app_state
+        .http_client()
+        .post(app_state.valhalla_router().locate_url())
+        .json(&locate_query)
+        .timeout(app_state.upstream_config().valhalla_timeout)
+        .send()
+        .await
+        .map_err(|e| {
+            log::error!("error while fetching locate from valhalla service: {e}");
+            PlanResponseErr::from(Error::server(e))
+        })?
+        .json()
+        .await
+        .map_err(|e| {
+            log::error!("error while parsing valhalla locate response: {e}");
+            PlanResponseErr::from(Error::server(e))
+        })?;
+
+    match response {
+        valhalla_api::LocateResponseResult::Ok(located) => {
+            if located.len() != places.len() {
+                return Err(Error::server(format!(
+                    "expected {} located places, got: {}",
+                    places.len(),
+                    located.len()
+                ))
+                .into());
+            }
+            Ok(located.iter().map(Snap::from_valhalla).collect())
+        }
+        valhalla_api::LocateResponseResult::Err(err) => Err(err.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+    use serde_json::json;
+
+    #[test]
+    fn snap_from_valhalla() {
+        let response: valhalla_api::LocateResponseResult = serde_json::from_value(json!([
+            {
+                "input_lat": 47.6,
+                "input_lon": -122.3,
+                "edges": [
+                    {
+                        "correlated_lat": 47.601,
+                        "correlated_lon": -122.3,
+                        "side_of_street": "left",
+                        "percent_along": 0.5,
+                        "edge_info": { "names": ["Far Avenue"], "way_id": 1 }
+                    },
+                    {
+                        "correlated_lat": 47.6001,
+                        "correlated_lon": -122.3,
+                        "side_of_street": "right",
+                        "percent_along": 0.2,
+                        "edge_info": { "names": ["Near Street", "SR 99"], "way_id": 2 }
+                    }
+                ],
+                "nodes": []
+            },
+            { "input_lat": 47.0, "input_lon": -123.0, "edges": null, "nodes": null }
+        ]))
+        .unwrap();
+        let valhalla_api::LocateResponseResult::Ok(located) = response else {
+            panic!("expected located places");
+        };
+
+        let snap = Snap::from_valhalla(&located[0]).unwrap();
+        assert_eq!(snap.street_name.as_deref(), Some("Near Street"));
+        assert_eq!(
+            snap.location,
+            LonLat {
+                lon: -122.3,
+                lat: 47.6001
+            }
+        );
+        assert_relative_eq!(snap.distance_meters, 11.1, epsilon = 0.1);
+        assert_eq!(
+            serde_json::to_value(&snap).unwrap(),
+            json!({
+                "lon": -122.3,
+                "lat": 47.6001,
+                "distanceMeters": snap.distance_meters,
+                "streetName": "Near Street"
+            })
+        );
+
+        assert_eq!(Snap::from_valhalla(&located[1]), None);
+    }
+
+    #[test]
+    fn parse_error_from_valhalla() {
+        let response: valhalla_api::LocateResponseResult = serde_json::from_value(json!({
+            "error_code": 120,
+            "error": "Insufficient number of locations provided",
+            "status_code": 400,
+            "status": "Bad Request"
+        }))
+        .unwrap();
+        assert!(matches!(
+            response,
+            valhalla_api::LocateResponseResult::Err(_)
+        ));
+    }
+}
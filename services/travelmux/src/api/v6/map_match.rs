@@ -0,0 +1,336 @@
+use super::error::{PlanResponseErr, PlanResponseOk};
+use crate::api::AppState;
+use crate::valhalla::valhalla_api;
+use crate::valhalla::valhalla_api::LonLat;
+use crate::{DistanceUnit, Error, Result, TravelMode};
+use actix_web::{post, web};
+use geo::Point;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use serde::Deserialize;
+
+/// Valhalla's default `max_shape` for `/trace_route`
+const MAX_TRACE_POINTS: usize = 16_000;
+const TRACE_POLYLINE_PRECISION: u32 = 6;
+/// Generous enough for a GPX track of `MAX_TRACE_POINTS`, even with elevations, timestamps and
+/// extensions on every point.
+const MAX_TRACE_BYTES: usize = MAX_TRACE_POINTS * 512;
+
+/// Allows request bodies big enough for a trace of `MAX_TRACE_POINTS`, rather than actix's
+/// default limit of 256 KiB.
+pub fn payload_config() -> web::PayloadConfig {
+    web::PayloadConfig::new(MAX_TRACE_BYTES)
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MatchQuery {
+    mode: TravelMode,
+
+    preferred_distance_units: Option<DistanceUnit>,
+
+    /// The language of turn-by-turn instructions, as a tag like `de` or `es-MX`.
+    lang: Option<String>,
+}
+
+/// Snap a recorded trace onto the road network, returning it as a plan with a single itinerary,
+/// just like `/v6/plan`.
+///
+/// The request body is the trace, either as a GPX document or as a polyline with precision 6.
+#[post("/v6/match")]
+pub async fn post_match(
+    query: web::Query<MatchQuery>,
+    body: String,
+    app_state: web::Data<AppState>,
+) -> std::result::Result<PlanResponseOk, PlanResponseErr> {
+    let trace = parse_trace(&body)?;
+    let trace_route_query = query.trace_route_query(&trace)?;
+
+    let response: valhalla_api::ValhallaRouteResponseResult = app_state
+        .http_client()
+        .post(app_state.valhalla_router().trace_route_url())
+        .json(&trace_route_query)
+        .timeout(app_state.upstream_config().valhalla_timeout)
+        .send()
+        .await
+        .map_err(|e| {
+            log::error!("error while fetching trace_route from valhalla service: {e}");
+            PlanResponseErr::from(Error::server(e))
+        })?
+        .json()
+        .await
+        .map_err(|e| {
+            log::error!("error while parsing valhalla trace_route response: {e}");
+            PlanResponseErr::from(Error::server(e))
+        })?;
+
+    Ok(PlanResponseOk::from_valhalla(query.mode, response, None)?)
+}
+
+impl MatchQuery {
+    fn trace_route_query(&self, trace: &[Point]) -> Result<valhalla_api::TraceRouteQuery> {
+        let costing = match self.mode {
+            TravelMode::Walk => valhalla_api::ModeCosting::Pedestrian,
+            TravelMode::Bicycle => valhalla_api::ModeCosting::Bicycle,
+            TravelMode::Car => valhalla_api::ModeCosting::Auto,
+            TravelMode::Transit => {
+                return Err(Error::user("transit traces can't be matched"));
+            }
+        };
+        Ok(valhalla_api::TraceRouteQuery {
+            shape: trace.iter().copied().map(LonLat::from).collect(),
+            costing,
+            shape_match: valhalla_api::ShapeMatch::MapSnap,
+            // NOTE: these units get embedded in the localized turn-by-turn direction strings
+            units: self
+                .preferred_distance_units
+                .unwrap_or(DistanceUnit::Kilometers),
+            language: self.lang.clone(),
+        })
+    }
+}
+
+/// Parse a trace from either a GPX document or a polyline6.
+fn parse_trace(body: &str) -> Result<Vec<Point>> {
+    let body = body.trim();
+    let trace = if body.starts_with('<') {
+        parse_gpx(body)?
+    } else {
+        polyline::decode_polyline(body, TRACE_POLYLINE_PRECISION)
+            .map_err(|e| Error::user(format!("invalid polyline: {e}")))?
+            .into_points()
+    };
+
+    if trace.len() < 2 {
+        return Err(Error::user("a trace needs at least two points"));
+    }
+    if trace.len() > MAX_TRACE_POINTS {
+        return Err(Error::user(format!(
+            "traces are limited to {MAX_TRACE_POINTS} points, got: {}",
+            trace.len()
+        )));
+    }
+    Ok(trace)
+}
+
+/// The track points of a GPX document, or its route points if it has no tracks.
+fn parse_gpx(gpx: &str) -> Result<Vec<Point>> {
+    let mut reader = Reader::from_str(gpx);
+    let mut track_points = vec![];
+    let mut route_points = vec![];
+    let mut is_gpx = false;
+    let mut depth = 0usize;
+    loop {
+        let event = reader.read_event().map_err(|e| {
+            Error::user(format!(
+                "invalid GPX at byte {}: {e}",
+                reader.error_position()
+            ))
+        })?;
+        match event {
+            Event::Start(ref element) | Event::Empty(ref element) => {
+                match element.local_name().as_ref() {
+                    b"gpx" => is_gpx = true,
+                    b"trkpt" => track_points.push(gpx_point(element)?),
+                    b"rtept" => route_points.push(gpx_point(element)?),
+                    _ => {}
+                }
+                if matches!(event, Event::Start(_)) {
+                    depth += 1;
+                }
+            }
+            Event::End(_) => depth -= 1,
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return Err(Error::user("invalid GPX: unclosed element"));
+    }
+    if !is_gpx {
+        return Err(Error::user("expected a <gpx> document"));
+    }
+
+    if track_points.is_empty() {
+        Ok(route_points)
+    } else {
+        Ok(track_points)
+    }
+}
+
+/// The location of a `<trkpt>` or `<rtept>`, from its `lat` and `lon` attributes.
+fn gpx_point(element: &BytesStart) -> Result<Point> {
+    let name = String::from_utf8_lossy(element.local_name().as_ref()).into_owned();
+    let mut lat = None;
+    let mut lon = None;
+    for attribute in element.attributes() {
+        let attribute =
+            attribute.map_err(|e| Error::user(format!("invalid attribute on <{name}>: {e}")))?;
+        let value = || -> Option<f64> { attribute.unescape_value().ok()?.trim().parse().ok() };
+        match attribute.key.local_name().as_ref() {
+            b"lat" => lat = value().filter(|lat| (-90.0..=90.0).contains(lat)),
+            b"lon" => lon = value().filter(|lon| (-180.0..=180.0).contains(lon)),
+            _ => {}
+        }
+    }
+    let lat = lat.ok_or_else(|| Error::user(format!("<{name}> is missing a valid lat")))?;
+    let lon = lon.ok_or_else(|| Error::user(format!("<{name}> is missing a valid lon")))?;
+    Ok(Point::new(lon, lat))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorType;
+    use crate::test_util::stub_upstream;
+    use actix_web::web::Query;
+    use serde_json::json;
+
+    #[test]
+    fn parse_gpx_trace() {
+        let gpx = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <gpx version="1.1" creator="test">
+              <trk><name>Morning walk</name><trkseg>
+                <trkpt lat="47.575837" lon="-122.339414"><ele>10</ele><time>2024-05-01T08:00:00Z</time></trkpt>
+                <trkpt lon='-122.3401' lat='47.5762'/>
+                <trkpt  lat = "47.5770"  lon = "-122.3410" ></trkpt>
+                <!-- <trkpt lat="1" lon="1"/> -->
+                <extensions><![CDATA[<trkpt lat="2" lon="2"/>]]></extensions>
+              </trkseg></trk>
+            </gpx>"#;
+        let trace = parse_trace(gpx).unwrap();
+        assert_eq!(
+            trace,
+            vec![
+                Point::new(-122.339414, 47.575837),
+                Point::new(-122.3401, 47.5762),
+                Point::new(-122.3410, 47.5770),
+            ]
+        );
+
+        let route = r#"<gpx><rte><rtept lat="1" lon="2"/><rtept lat="3" lon="4"/></rte></gpx>"#;
+        assert_eq!(
+            parse_trace(route).unwrap(),
+            vec![Point::new(2.0, 1.0), Point::new(4.0, 3.0)]
+        );
+
+        for bad in [
+            r#"<gpx><trk><trkseg><trkpt lat="47.5" lon="-122.3"/></trkseg></trk></gpx>"#,
+            r#"<gpx><trk><trkseg><trkpt lat="47.5"/><trkpt lat="47.6"/></trkseg></trk></gpx>"#,
+            r#"<gpx><trk><trkseg><trkpt lat="north" lon="-122.3"/></trkseg></trk></gpx>"#,
+            r#"<gpx><trk><trkseg><trkpt lat="147.5" lon="-122.3"/><trkpt lat="47.6" lon="-122.3"/></trkseg></trk></gpx>"#,
+            r#"<gpx><trk><trkseg><trkpt lat="47.5" lon="-122.3"/><trkpt lat="47.6" lon="-122.3"/></trk></gpx>"#,
+            r#"<gpx><trk><trkseg><trkpt lat="47.5" lon="-122.3"/><trkpt lat="47.6" lon="-122.3"/>"#,
+            r#"<kml><trkpt lat="47.5" lon="-122.3"/><trkpt lat="47.6" lon="-122.3"/></kml>"#,
+            r#"</trk><gpx><trkpt lat="47.5" lon="-122.3"/><trkpt lat="47.6" lon="-122.3"/></gpx>"#,
+        ] {
+            let err = parse_trace(bad).unwrap_err();
+            assert_eq!(err.error_type, ErrorType::User, "{bad}");
+        }
+    }
+
+    #[test]
+    fn parse_polyline_trace() {
+        let line = geo::LineString::from(vec![(-122.339414, 47.575837), (-122.3401, 47.5762)]);
+        let encoded = polyline::encode_coordinates(line, 6).unwrap();
+        let trace = parse_trace(&format!("{encoded}\n")).unwrap();
+        assert_eq!(
+            trace,
+            vec![
+                Point::new(-122.339414, 47.575837),
+                Point::new(-122.3401, 47.5762)
+            ]
+        );
+        assert!(parse_trace("").is_err());
+    }
+
+    #[test]
+    fn trace_route_query_from_query() {
+        let trace = [Point::new(-122.3, 47.6), Point::new(-122.31, 47.61)];
+        let query =
+            Query::<MatchQuery>::from_query("mode=BICYCLE&preferredDistanceUnits=miles&lang=de")
+                .unwrap();
+        assert_eq!(
+            serde_json::to_value(query.trace_route_query(&trace).unwrap()).unwrap(),
+            json!({
+                "shape": [{ "lon": -122.3, "lat": 47.6 }, { "lon": -122.31, "lat": 47.61 }],
+                "costing": "bicycle",
+                "shape_match": "map_snap",
+                "units": "miles",
+                "language": "de"
+            })
+        );
+
+        let transit = Query::<MatchQuery>::from_query("mode=TRANSIT").unwrap();
+        assert!(transit.trace_route_query(&trace).is_err());
+    }
+
+    #[actix_web::test]
+    async fn match_trace() {
+        // Responds to every request with a recorded pedestrian route, which shares its shape
+        // with a `/trace_route` response.
+        let body =
+            std::fs::read_to_string("tests/fixtures/requests/valhalla_pedestrian_route.json")
+                .unwrap();
+        let valhalla_endpoint = stub_upstream(move |_request_line| (200, body.clone()));
+        let app_state = AppState::new(
+            valhalla_endpoint,
+            std::path::PathBuf::from("tests/fixtures/low_res_elevation_tifs"),
+        );
+        let app = actix_web::test::init_service(
+            actix_web::App::new()
+                .app_data(web::Data::new(app_state))
+                .app_data(payload_config())
+                .service(post_match),
+        )
+        .await;
+
+        let gpx = r#"<gpx><trk><trkseg>
+            <trkpt lat="47.575837" lon="-122.339414"/>
+            <trkpt lat="47.651048" lon="-122.347234"/>
+        </trkseg></trk></gpx>"#;
+        let request = actix_web::test::TestRequest::post()
+            .uri("/v6/match?mode=WALK")
+            .insert_header(("content-type", "application/gpx+xml"))
+            .set_payload(gpx)
+            .to_request();
+        let response: serde_json::Value =
+            actix_web::test::call_and_read_body_json(&app, request).await;
+
+        let leg = &response["plan"]["itineraries"][0]["legs"][0];
+        assert_eq!(leg["mode"], "WALK");
+        assert!(!leg["nonTransitLeg"]["maneuvers"]
+            .as_array()
+            .unwrap()
+            .is_empty());
+
+        // A recording of a couple hours at one point per second, well over actix's default
+        // payload limit.
+        let track_points: String = (0..MAX_TRACE_POINTS / 2)
+            .map(|i| {
+                format!(
+                    r#"<trkpt lat="{:.6}" lon="{:.6}"><ele>{}.4</ele><time>2024-05-01T08:{:02}:{:02}Z</time></trkpt>
+"#,
+                    47.575837 + i as f64 * 1e-5,
+                    -122.339414 - i as f64 * 1e-6,
+                    10 + i % 50,
+                    i / 60 % 60,
+                    i % 60
+                )
+            })
+            .collect();
+        let gpx = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<gpx version="1.1" creator="test"><trk><trkseg>
+{track_points}</trkseg></trk></gpx>"#
+        );
+        assert!(gpx.len() > 512 * 1024, "{}", gpx.len());
+        let request = actix_web::test::TestRequest::post()
+            .uri("/v6/match?mode=WALK")
+            .insert_header(("content-type", "application/gpx+xml"))
+            .set_payload(gpx)
+            .to_request();
+        let response = actix_web::test::call_service(&app, request).await;
+        assert_eq!(response.status(), 200);
+    }
+}
@@ -0,0 +1,396 @@
+use super::error::{PlanError, PlanResponseErr};
+use super::plan::{fetch_otp_plan, format_lat_lon};
+use crate::api::AppState;
+use crate::error::ErrorType;
+use crate::otp::otp_api;
+use crate::util::convert_from_meters;
+use crate::util::serde_util::deserialize_points_from_lat_lon_list;
+use crate::valhalla::valhalla_api;
+use crate::valhalla::valhalla_api::LonLat;
+use crate::{DistanceUnit, Error, TravelMode};
+use actix_web::{get, web, HttpResponse};
+use futures_util::StreamExt;
+use geo::Point;
+use serde::{Deserialize, Serialize};
+
+/// Valhalla's default limit for sources and for targets
+const MAX_VALHALLA_LOCATIONS: usize = 50;
+/// Each transit cell is a separate OTP plan, so keep these small
+const MAX_TRANSIT_CELLS: usize = 25;
+const MAX_CONCURRENT_OTP_PLANS: usize = 4;
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MatrixQuery {
+    /// Formatted as `lat,lon|lat,lon`
+    #[serde(deserialize_with = "deserialize_points_from_lat_lon_list")]
+    sources: Vec<Point>,
+
+    /// Formatted as `lat,lon|lat,lon`
+    #[serde(deserialize_with = "deserialize_points_from_lat_lon_list")]
+    targets: Vec<Point>,
+
+    mode: TravelMode,
+
+    preferred_distance_units: Option<DistanceUnit>,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MatrixResponse {
+    distance_units: DistanceUnit,
+    /// Indexed by source, then by target
+    cells: Vec<Vec<MatrixCell>>,
+}
+
+/// The trip from one source to one target. Either `durationSeconds` and `distance` are set, or
+/// `error` explains why there's no trip.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MatrixCell {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration_seconds: Option<f64>,
+    /// In units of the response's `distance_units`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    distance: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<CellError>,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CellError {
+    status_code: u16,
+    error_code: u32,
+    message: String,
+}
+
+impl From<PlanError> for CellError {
+    fn from(value: PlanError) -> Self {
+        Self {
+            status_code: value.status_code,
+            error_code: value.error_code,
+            message: value.message,
+        }
+    }
+}
+
+impl MatrixCell {
+    fn ok(duration_seconds: f64, distance: f64) -> Self {
+        Self {
+            duration_seconds: Some(duration_seconds),
+            distance: Some(distance),
+            error: None,
+        }
+    }
+
+    fn err(error: impl Into<PlanError>) -> Self {
+        Self {
+            duration_seconds: None,
+            distance: None,
+            error: Some(CellError::from(error.into())),
+        }
+    }
+
+    /// Valhalla's error for when it can't find a path between two locations.
+    fn no_valhalla_route() -> Self {
+        Self::err(&valhalla_api::RouteResponseError {
+            status_code: 400,
+            error_code: 442,
+            error: "No path could be found for input".to_string(),
+            extra: Default::default(),
+        })
+    }
+
+    /// OTP's error for when it can't find a trip between two locations.
+    fn no_otp_route() -> Self {
+        Self::err(&otp_api::PlanError {
+            id: 404,
+            msg: "No trip found.".to_string(),
+            message: "PATH_NOT_FOUND".to_string(),
+            extra: Default::default(),
+        })
+    }
+}
+
+/// Durations and distances from each of the `sources` to each of the `targets`.
+#[get("/v6/matrix")]
+pub async fn get_matrix(
+    query: web::Query<MatrixQuery>,
+    app_state: web::Data<AppState>,
+) -> std::result::Result<HttpResponse, PlanResponseErr> {
+    if query.sources.is_empty() || query.targets.is_empty() {
+        return Err(Error::user("sources and targets are required").into());
+    }
+    let distance_units = query
+        .preferred_distance_units
+        .unwrap_or(DistanceUnit::Kilometers);
+
+    let cells = match query.mode {
+        TravelMode::Transit => otp_matrix(&query, distance_units, &app_state).await?,
+        TravelMode::Walk | TravelMode::Bicycle | TravelMode::Car => {
+            valhalla_matrix(&query, distance_units, &app_state).await?
+        }
+    };
+    Ok(HttpResponse::Ok().json(MatrixResponse {
+        distance_units,
+        cells,
+    }))
+}
+
+async fn valhalla_matrix(
+    query: &MatrixQuery,
+    distance_units: DistanceUnit,
+    app_state: &AppState,
+) -> std::result::Result<Vec<Vec<MatrixCell>>, PlanResponseErr> {
+    if query.sources.len() > MAX_VALHALLA_LOCATIONS || query.targets.len() > MAX_VALHALLA_LOCATIONS
+    {
+        return Err(Error::user(format!(
+            "at most {MAX_VALHALLA_LOCATIONS} sources and {MAX_VALHALLA_LOCATIONS} targets are supported"
+        ))
+        .into());
+    }
+    let costing = match query.mode {
+        TravelMode::Walk => valhalla_api::ModeCosting::Pedestrian,
+        TravelMode::Bicycle => valhalla_api::ModeCosting::Bicycle,
+        TravelMode::Car => valhalla_api::ModeCosting::Auto,
+        TravelMode::Transit => unreachable!("transit matrices come from OTP"),
+    };
+    let matrix_query = valhalla_api::MatrixQuery {
+        sources: query.sources.iter().copied().map(LonLat::from).collect(),
+        targets: query.targets.iter().copied().map(LonLat::from).collect(),
+        costing,
+        units: DistanceUnit::Kilometers,
+    };
+
+    let matrix = fetch_valhalla_matrix(app_state, &matrix_query).await?;
+    Ok(cells_from_valhalla(matrix, distance_units))
+}
+
+pub(super) async fn fetch_valhalla_matrix(
+    app_state: &AppState,
+    matrix_query: &valhalla_api::MatrixQuery,
+) -> std::result::Result<valhalla_api::MatrixResponse, PlanResponseErr> {
+    let response: valhalla_api::MatrixResponseResult = app_state
+        .http_client()
+        .post(app_state.valhalla_router().matrix_url())
+        .json(matrix_query)
+        .timeout(app_state.upstream_config().valhalla_timeout)
+        .send()
+        .await
+        .map_err(|e| {
+            log::error!("error while fetching matrix from valhalla service: {e}");
+            PlanResponseErr::from(Error::server(e))
+        })?
+        .json()
+        .await
+        .map_err(|e| {
+            log::error!("error while parsing valhalla matrix response: {e}");
+            PlanResponseErr::from(Error::server(e))
+        })?;
+
+    match response {
+        valhalla_api::MatrixResponseResult::Ok(matrix) => Ok(matrix),
+        valhalla_api::MatrixResponseResult::Err(err) => Err(err.into()),
+    }
+}
+
+fn cells_from_valhalla(
+    matrix: valhalla_api::MatrixResponse,
+    distance_units: DistanceUnit,
+) -> Vec<Vec<MatrixCell>> {
+    debug_assert_eq!(matrix.units, DistanceUnit::Kilometers);
+    matrix
+        .sources_to_targets
+        .into_iter()
+        .map(|row| {
+            row.into_iter()
+                .map(|cell| match (cell.time, cell.distance) {
+                    (Some(time), Some(kilometers)) => MatrixCell::ok(
+                        time,
+                        convert_from_meters(kilometers * 1000.0, distance_units),
+                    ),
+                    _ => MatrixCell::no_valhalla_route(),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Plans a transit trip for every cell, departing now.
+async fn otp_matrix(
+    query: &MatrixQuery,
+    distance_units: DistanceUnit,
+    app_state: &AppState,
+) -> std::result::Result<Vec<Vec<MatrixCell>>, PlanResponseErr> {
+    let cell_count = query.sources.len() * query.targets.len();
+    if cell_count > MAX_TRANSIT_CELLS {
+        return Err(Error::user(format!(
+            "transit matrices support at most {MAX_TRANSIT_CELLS} cells, got: {cell_count}"
+        ))
+        .into());
+    }
+
+    let pairs: Vec<(Point, Point)> = query
+        .sources
+        .iter()
+        .flat_map(|&source| query.targets.iter().map(move |&target| (source, target)))
+        .collect();
+    let cells: Vec<MatrixCell> = futures_util::stream::iter(pairs)
+        .map(|(source, target)| otp_cell(source, target, distance_units, app_state))
+        .buffered(MAX_CONCURRENT_OTP_PLANS)
+        .collect()
+        .await;
+
+    Ok(cells
+        .chunks(query.targets.len())
+        .map(|row| row.to_vec())
+        .collect())
+}
+
+async fn otp_cell(
+    source: Point,
+    target: Point,
+    distance_units: DistanceUnit,
+    app_state: &AppState,
+) -> MatrixCell {
+    let Some(plan_endpoint) = app_state
+        .otp_cluster()
+        .find_plan_endpoints_for_waypoints(&[source, target])
+        .into_iter()
+        .next()
+    else {
+        return MatrixCell::err(
+            Error::user("Transit directions not available for this area.")
+                .error_type(ErrorType::NoCoverageForArea),
+        );
+    };
+
+    let plan_request = otp_api::PlanRequest {
+        from_place: format_lat_lon(source),
+        to_place: format_lat_lon(target),
+        mode: vec![otp_api::RequestMode::Transit, otp_api::RequestMode::Walk],
+        num_itineraries: 1,
+        date: None,
+        time: None,
+        arrive_by: false,
+        wheelchair: false,
+        walk_speed: None,
+        max_walk_distance: None,
+    };
+    match fetch_otp_plan(&plan_endpoint, &plan_request, app_state).await {
+        Ok(plan_response) => cell_from_otp(plan_response, distance_units),
+        Err(err) => MatrixCell::err(err.error),
+    }
+}
+
+fn cell_from_otp(plan_response: otp_api::PlanResponse, distance_units: DistanceUnit) -> MatrixCell {
+    if let Some(error) = &plan_response.error {
+        return MatrixCell::err(error);
+    }
+    let Some(itinerary) = plan_response
+        .plan
+        .itineraries
+        .iter()
+        .min_by_key(|itinerary| itinerary.duration)
+    else {
+        return MatrixCell::no_otp_route();
+    };
+    let distance_meters: f64 = itinerary.legs.iter().map(|leg| leg.distance).sum();
+    MatrixCell::ok(
+        itinerary.duration as f64,
+        convert_from_meters(distance_meters, distance_units),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+    use serde_json::json;
+    use std::fs::File;
+    use std::io::BufReader;
+
+    #[test]
+    fn parse_valhalla_matrix() {
+        let response: valhalla_api::MatrixResponseResult = serde_json::from_value(json!({
+            "sources_to_targets": [[
+                { "distance": 1.5, "time": 300, "from_index": 0, "to_index": 0 },
+                { "distance": null, "time": null, "from_index": 0, "to_index": 1 }
+            ]],
+            "units": "kilometers",
+            "algorithm": "costmatrix"
+        }))
+        .unwrap();
+        let valhalla_api::MatrixResponseResult::Ok(matrix) = response else {
+            panic!("expected a matrix");
+        };
+
+        let cells = cells_from_valhalla(matrix, DistanceUnit::Meters);
+        assert_eq!(cells.len(), 1);
+        assert_eq!(cells[0][0], MatrixCell::ok(300.0, 1500.0));
+
+        let json = serde_json::to_value(&cells[0][1]).unwrap();
+        assert!(json.get("durationSeconds").is_none());
+        assert_eq!(json["error"]["errorCode"], 2442);
+    }
+
+    #[test]
+    fn parse_otp_cell() {
+        let stubbed_response =
+            File::open("tests/fixtures/requests/opentripplanner_transit_plan.json").unwrap();
+        let otp: otp_api::PlanResponse =
+            serde_json::from_reader(BufReader::new(stubbed_response)).unwrap();
+        let fastest = otp
+            .plan
+            .itineraries
+            .iter()
+            .min_by_key(|itinerary| itinerary.duration)
+            .unwrap();
+        let duration = fastest.duration as f64;
+        let meters: f64 = fastest.legs.iter().map(|leg| leg.distance).sum();
+
+        let cell = cell_from_otp(otp, DistanceUnit::Kilometers);
+        assert_eq!(cell.duration_seconds, Some(duration));
+        assert_relative_eq!(cell.distance.unwrap(), meters / 1000.0);
+        assert!(cell.error.is_none());
+
+        let no_path: otp_api::PlanResponse = serde_json::from_value(json!({
+            "plan": { "itineraries": [] },
+            "error": { "id": 404, "msg": "PATH_NOT_FOUND", "message": "PATH_NOT_FOUND" }
+        }))
+        .unwrap();
+        let cell = cell_from_otp(no_path, DistanceUnit::Kilometers);
+        assert_eq!(cell.error.unwrap().error_code, 404);
+
+        // No itineraries, but no error either
+        let no_itineraries: otp_api::PlanResponse =
+            serde_json::from_value(json!({ "plan": { "itineraries": [] } })).unwrap();
+        let error = cell_from_otp(no_itineraries, DistanceUnit::Kilometers)
+            .error
+            .unwrap();
+        assert_eq!(error.status_code, 400);
+        assert_eq!(error.error_code, 404);
+    }
+
+    #[actix_web::test]
+    async fn transit_cells_without_coverage() {
+        let app_state = AppState::new(
+            url::Url::parse("http://127.0.0.1:1").unwrap(),
+            std::path::PathBuf::from("tests/fixtures/low_res_elevation_tifs"),
+        );
+        let query = web::Query::<MatrixQuery>::from_query(
+            "sources=47.6,-122.3&targets=47.61,-122.31|47.62,-122.32&mode=TRANSIT",
+        )
+        .unwrap();
+        let cells = otp_matrix(&query, DistanceUnit::Kilometers, &app_state)
+            .await
+            .unwrap();
+        assert_eq!(cells.len(), 1);
+        assert_eq!(cells[0].len(), 2);
+        for cell in &cells[0] {
+            let error = cell.error.as_ref().unwrap();
+            assert_eq!(error.error_code, ErrorType::NoCoverageForArea as u32);
+        }
+    }
+}
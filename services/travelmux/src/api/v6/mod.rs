@@ -1,6 +1,12 @@
+pub mod coverage;
 pub mod directions;
 pub mod elevation;
 mod error;
+pub mod isochrone;
+pub mod locate;
+pub mod map_match;
+pub mod matrix;
+pub mod optimized_route;
 mod osrm_api;
 pub mod plan;
 mod travel_modes;
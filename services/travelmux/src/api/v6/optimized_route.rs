@@ -0,0 +1,332 @@
+use super::error::PlanResponseErr;
+use super::matrix::fetch_valhalla_matrix;
+use super::plan::fetch_valhalla_route;
+use super::Itinerary;
+use crate::api::AppState;
+use crate::util::serde_util::{
+    deserialize_optional_point_from_lat_lon, deserialize_point_from_lat_lon,
+    deserialize_points_from_lat_lon_list,
+};
+use crate::valhalla::valhalla_api;
+use crate::valhalla::valhalla_api::LonLat;
+use crate::{DistanceUnit, Error, Result, TravelMode};
+use actix_web::{get, web, HttpResponse};
+use geo::Point;
+use serde::{Deserialize, Serialize};
+
+/// Without a fixed end, we find the best order ourselves, which gets exponentially more
+/// expensive with each stop.
+const MAX_STOPS: usize = 12;
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct OptimizedRouteQuery {
+    #[serde(deserialize_with = "deserialize_point_from_lat_lon")]
+    from_place: Point,
+
+    /// Where the trip must end, after visiting every stop. If omitted, the trip ends at
+    /// whichever stop makes for the shortest trip.
+    #[serde(default, deserialize_with = "deserialize_optional_point_from_lat_lon")]
+    to_place: Option<Point>,
+
+    /// The stops to visit, in any order. Formatted as `lat,lon|lat,lon`.
+    #[serde(deserialize_with = "deserialize_points_from_lat_lon_list")]
+    stops: Vec<Point>,
+
+    mode: TravelMode,
+
+    preferred_distance_units: Option<DistanceUnit>,
+
+    /// The language of turn-by-turn instructions, as a tag like `de` or `es-MX`.
+    lang: Option<String>,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct OptimizedRouteResponse {
+    /// Indices into the requested `stops`, in the order they are visited
+    stop_order: Vec<usize>,
+    itinerary: Itinerary,
+}
+
+impl OptimizedRouteQuery {
+    fn costing(&self) -> Result<valhalla_api::ModeCosting> {
+        if self.stops.is_empty() {
+            return Err(Error::user("at least one stop is required"));
+        }
+        if self.stops.len() > MAX_STOPS {
+            return Err(Error::user(format!(
+                "at most {MAX_STOPS} stops are supported, got: {}",
+                self.stops.len()
+            )));
+        }
+        match self.mode {
+            TravelMode::Walk => Ok(valhalla_api::ModeCosting::Pedestrian),
+            TravelMode::Bicycle => Ok(valhalla_api::ModeCosting::Bicycle),
+            TravelMode::Car => Ok(valhalla_api::ModeCosting::Auto),
+            TravelMode::Transit => Err(Error::user("transit routes can't be optimized")),
+        }
+    }
+
+    fn distance_units(&self) -> DistanceUnit {
+        self.preferred_distance_units
+            .unwrap_or(DistanceUnit::Kilometers)
+    }
+}
+
+/// The quickest order in which to visit every one of the `stops`, and the itinerary which
+/// visits them in that order, starting at `fromPlace` and ending at `toPlace`, if given.
+#[get("/v6/optimized_route")]
+pub async fn get_optimized_route(
+    query: web::Query<OptimizedRouteQuery>,
+    app_state: web::Data<AppState>,
+) -> std::result::Result<HttpResponse, PlanResponseErr> {
+    let costing = query.costing()?;
+    let (stop_order, route) = match query.to_place {
+        Some(to_place) => valhalla_optimized_route(&query, to_place, costing, &app_state).await?,
+        None => open_ended_route(&query, costing, &app_state).await?,
+    };
+    let itinerary = Itinerary::from_valhalla(&route.trip, query.mode, None);
+    Ok(HttpResponse::Ok().json(OptimizedRouteResponse {
+        stop_order,
+        itinerary,
+    }))
+}
+
+/// Valhalla can optimize the order of the stops between a fixed start and end.
+async fn valhalla_optimized_route(
+    query: &OptimizedRouteQuery,
+    to_place: Point,
+    costing: valhalla_api::ModeCosting,
+    app_state: &AppState,
+) -> std::result::Result<(Vec<usize>, valhalla_api::RouteResponse), PlanResponseErr> {
+    let mut locations = vec![LonLat::from(query.from_place)];
+    locations.extend(query.stops.iter().copied().map(LonLat::from));
+    locations.push(LonLat::from(to_place));
+    let optimized_route_query = valhalla_api::OptimizedRouteQuery {
+        locations,
+        costing,
+        // NOTE: these units get embedded in the localized turn-by-turn direction strings
+        units: query.distance_units(),
+        language: query.lang.clone(),
+    };
+
+    let response: valhalla_api::ValhallaRouteResponseResult = app_state
+        .http_client()
+        .post(app_state.valhalla_router().optimized_route_url())
+        .json(&optimized_route_query)
+        .timeout(app_state.upstream_config().valhalla_timeout)
+        .send()
+        .await
+        .map_err(|e| {
+            log::error!("error while fetching optimized_route from valhalla service: {e}");
+            PlanResponseErr::from(Error::server(e))
+        })?
+        .json()
+        .await
+        .map_err(|e| {
+            log::error!("error while parsing valhalla optimized_route response: {e}");
+            PlanResponseErr::from(Error::server(e))
+        })?;
+    let route = match response {
+        valhalla_api::ValhallaRouteResponseResult::Ok(route) => route,
+        valhalla_api::ValhallaRouteResponseResult::Err(err) => return Err(err.into()),
+    };
+    let stop_order = stop_order_from_trip(&route.trip)?;
+    Ok((stop_order, route))
+}
+
+/// The order of the stops, as reordered by `/optimized_route`.
+fn stop_order_from_trip(trip: &valhalla_api::Trip) -> Result<Vec<usize>> {
+    let Some((_, stops)) = trip.locations.split_first() else {
+        return Err(Error::server("optimized trip had no locations"));
+    };
+    let Some((_, stops)) = stops.split_last() else {
+        return Err(Error::server("optimized trip had no end location"));
+    };
+    stops
+        .iter()
+        .map(|location| {
+            // The first stop follows the start location
+            location
+                .original_index
+                .and_then(|index| index.checked_sub(1))
+                .ok_or_else(|| Error::server("optimized trip location is missing original_index"))
+        })
+        .collect()
+}
+
+/// Without a fixed end, pick the order ourselves from the travel times between every stop, then
+/// route through the stops in that order.
+async fn open_ended_route(
+    query: &OptimizedRouteQuery,
+    costing: valhalla_api::ModeCosting,
+    app_state: &AppState,
+) -> std::result::Result<(Vec<usize>, valhalla_api::RouteResponse), PlanResponseErr> {
+    let stop_order = if query.stops.len() == 1 {
+        vec![0]
+    } else {
+        let mut locations = vec![LonLat::from(query.from_place)];
+        locations.extend(query.stops.iter().copied().map(LonLat::from));
+        let matrix_query = valhalla_api::MatrixQuery {
+            sources: locations.clone(),
+            targets: locations,
+            costing,
+            units: DistanceUnit::Kilometers,
+        };
+        let matrix = fetch_valhalla_matrix(app_state, &matrix_query).await?;
+        let times: Vec<Vec<Option<f64>>> = matrix
+            .sources_to_targets
+            .iter()
+            .map(|row| row.iter().map(|cell| cell.time).collect())
+            .collect();
+        let Some(stop_order) = quickest_open_path(&times) else {
+            return Err(Error::user("No route visits every stop.").into());
+        };
+        stop_order
+    };
+
+    let mut waypoints = vec![query.from_place];
+    waypoints.extend(stop_order.iter().map(|&stop| query.stops[stop]));
+    let mut route_query = app_state.valhalla_router().route_query(
+        &waypoints,
+        costing,
+        0,
+        query.distance_units(),
+        None,
+    );
+    route_query.language = query.lang.clone();
+    match fetch_valhalla_route(app_state, &route_query).await? {
+        valhalla_api::ValhallaRouteResponseResult::Ok(route) => Ok((stop_order, route)),
+        valhalla_api::ValhallaRouteResponseResult::Err(err) => Err(err.into()),
+    }
+}
+
+/// The order in which to visit every stop, starting from location 0, for the least total time.
+///
+/// `times[a][b]` is the time from location `a` to location `b`, or `None` if there's no route,
+/// where stop `i` is location `i + 1`. Returns `None` if no order visits every stop.
+fn quickest_open_path(times: &[Vec<Option<f64>>]) -> Option<Vec<usize>> {
+    let stop_count = times.len() - 1;
+    let all_stops = (1usize << stop_count) - 1;
+
+    // Held-Karp: `best[visited][last]` is the quickest time to visit the `visited` set of
+    // stops, ending at `last`, along with the stop visited just before `last`.
+    type Best = Option<(f64, Option<usize>)>;
+    let mut best: Vec<Vec<Best>> = vec![vec![None; stop_count]; 1 << stop_count];
+    for stop in 0..stop_count {
+        best[1 << stop][stop] = times[0][stop + 1].map(|time| (time, None));
+    }
+    for visited in 1..=all_stops {
+        for last in 0..stop_count {
+            let Some((time, _)) = best[visited][last] else {
+                continue;
+            };
+            for next in (0..stop_count).filter(|next| visited & (1 << next) == 0) {
+                let Some(step) = times[last + 1][next + 1] else {
+                    continue;
+                };
+                let candidate = best[visited | (1 << next)][next].get_or_insert((f64::MAX, None));
+                if time + step < candidate.0 {
+                    *candidate = (time + step, Some(last));
+                }
+            }
+        }
+    }
+
+    let mut last = (0..stop_count)
+        .filter_map(|last| Some((last, best[all_stops][last]?.0)))
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))?
+        .0;
+    let mut visited = all_stops;
+    let mut order = Vec::with_capacity(stop_count);
+    loop {
+        order.push(last);
+        let (_, previous) = best[visited][last].expect("visited along the quickest path");
+        visited &= !(1 << last);
+        match previous {
+            Some(previous) => last = previous,
+            None => break,
+        }
+    }
+    order.reverse();
+    Some(order)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorType;
+    use actix_web::web::Query;
+    use serde_json::json;
+
+    #[test]
+    fn parse_query() {
+        let query = Query::<OptimizedRouteQuery>::from_query(
+            "fromPlace=47.6,-122.3&stops=47.61,-122.31|47.62,-122.32&mode=CAR",
+        )
+        .unwrap();
+        assert_eq!(query.to_place, None);
+        assert_eq!(query.stops.len(), 2);
+        assert_eq!(query.costing().unwrap(), valhalla_api::ModeCosting::Auto);
+
+        let query = Query::<OptimizedRouteQuery>::from_query(
+            "fromPlace=47.6,-122.3&toPlace=47.7,-122.4&stops=47.61,-122.31&mode=WALK",
+        )
+        .unwrap();
+        assert_eq!(query.to_place, Some(Point::new(-122.4, 47.7)));
+
+        let too_many = vec!["47.61,-122.31"; MAX_STOPS + 1].join("|");
+        for bad in [
+            "fromPlace=47.6,-122.3&stops=&mode=WALK".to_string(),
+            "fromPlace=47.6,-122.3&stops=47.61,-122.31&mode=TRANSIT".to_string(),
+            format!("fromPlace=47.6,-122.3&stops={too_many}&mode=WALK"),
+        ] {
+            let query = Query::<OptimizedRouteQuery>::from_query(&bad).unwrap();
+            assert_eq!(query.costing().unwrap_err().error_type, ErrorType::User);
+        }
+    }
+
+    #[test]
+    fn quickest_order() {
+        // Stops lie on a line, out of order: start=0, A=30, B=10, C=20
+        let positions = [0.0, 30.0, 10.0, 20.0_f64];
+        let times: Vec<Vec<Option<f64>>> = positions
+            .iter()
+            .map(|a| positions.iter().map(|b| Some((a - b).abs())).collect())
+            .collect();
+        assert_eq!(quickest_open_path(&times), Some(vec![1, 2, 0]));
+
+        // A one-way street makes it quicker to visit B first
+        let times = vec![
+            vec![Some(0.0), Some(1.0), Some(2.0)],
+            vec![Some(1.0), Some(0.0), Some(10.0)],
+            vec![Some(2.0), Some(1.0), Some(0.0)],
+        ];
+        assert_eq!(quickest_open_path(&times), Some(vec![1, 0]));
+
+        // Nothing reaches B
+        let times = vec![
+            vec![Some(0.0), Some(1.0), None],
+            vec![Some(1.0), Some(0.0), None],
+            vec![None, None, Some(0.0)],
+        ];
+        assert_eq!(quickest_open_path(&times), None);
+    }
+
+    #[test]
+    fn stop_order_from_optimized_trip() {
+        let location = |original_index: usize| json!({ "lat": 47.6, "lon": -122.3, "original_index": original_index });
+        let trip: valhalla_api::Trip = serde_json::from_value(json!({
+            "locations": [location(0), location(3), location(1), location(2), location(4)],
+            "summary": {
+                "length": 1.0, "time": 60.0,
+                "min_lat": 47.6, "min_lon": -122.3, "max_lat": 47.6, "max_lon": -122.3
+            },
+            "units": "kilometers",
+            "legs": []
+        }))
+        .unwrap();
+        assert_eq!(stop_order_from_trip(&trip).unwrap(), vec![2, 0, 1]);
+    }
+}
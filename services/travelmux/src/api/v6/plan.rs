@@ -1,9 +1,11 @@
-use super::error::{PlanResponseErr, PlanResponseOk};
+use super::error::{CoverageHint, PlanResponseErr, PlanResponseOk};
+use super::locate::{fetch_snaps, EndpointSnaps, Snap};
+use super::travel_modes::TransitAccess;
 use super::TravelModes;
 use actix_web::web::{Data, Query};
 use actix_web::{get, web, HttpRequest, HttpResponseBuilder};
 use geo::algorithm::BoundingRect;
-use geo::geometry::{LineString, Point, Rect};
+use geo::geometry::{LineString, Point, Polygon, Rect};
 use polyline::decode_polyline;
 use polyline::errors::PolylineError;
 use reqwest::header::{HeaderName, HeaderValue};
@@ -13,12 +15,14 @@ use std::time::{Duration, SystemTime};
 
 use crate::api::AppState;
 use crate::error::ErrorType;
-use crate::otp::otp_api;
+use crate::otp::{otp_api, otp_graphql, OtpApi, OtpPlanEndpoint};
 use crate::util::format::format_meters;
 use crate::util::haversine_segmenter::HaversineSegmenter;
+use crate::util::i18n::Language;
 use crate::util::serde_util::{
-    deserialize_point_from_lat_lon, serialize_line_string_as_polyline6, serialize_rect_to_lng_lat,
-    serialize_system_time_as_millis,
+    deserialize_point_from_lat_lon, deserialize_points_from_lat_lon_list,
+    deserialize_polygons_from_geojson_or_polyline6, serialize_line_string_as_polyline6,
+    serialize_rect_to_lng_lat, serialize_system_time_as_millis,
 };
 use crate::util::{
     bearing_at_end, bearing_at_start, convert_from_meters, convert_to_meters, extend_bounds,
@@ -27,6 +31,7 @@ use crate::util::{
 use crate::valhalla::valhalla_api;
 use crate::valhalla::valhalla_api::{LonLat, ManeuverType};
 use crate::{DistanceUnit, Error, TravelMode};
+use chrono_tz::Tz;
 
 #[derive(Debug, Deserialize, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
@@ -37,6 +42,11 @@ pub struct PlanQuery {
     #[serde(deserialize_with = "deserialize_point_from_lat_lon")]
     from_place: Point,
 
+    /// Intermediate stops, visited in order between `from_place` and `to_place`.
+    /// Formatted as `lat,lon|lat,lon`. Each stop begins a new leg of the trip.
+    #[serde(default, deserialize_with = "deserialize_points_from_lat_lon_list")]
+    via: Vec<Point>,
+
     num_itineraries: u32,
 
     mode: TravelModes,
@@ -44,6 +54,332 @@ pub struct PlanQuery {
     /// Ignored by OTP - transit trips will always be metric.
     /// Examine the `distance_units` in the response `Itinerary` to correctly interpret the response.
     preferred_distance_units: Option<DistanceUnit>,
+
+    /// Local date of the trip, formatted as `YYYY-MM-DD` (or OTP's `MM-DD-YYYY`).
+    /// Must be accompanied by `time`. If omitted, the trip departs now.
+    date: Option<String>,
+
+    /// Local time of the trip, formatted as `HH:MM` (or OTP's `h:mmam`).
+    /// Must be accompanied by `date`.
+    time: Option<String>,
+
+    /// When true, `date` and `time` are the latest time to arrive at the destination,
+    /// rather than the earliest time to depart from the origin.
+    #[serde(default)]
+    arrive_by: bool,
+
+    /// Only return routes which are wheelchair accessible. Applies to walking and transit trips.
+    #[serde(default)]
+    wheelchair: bool,
+
+    /// Walking speed, in meters per second. Only supported by OTP.
+    walk_speed: Option<f64>,
+
+    /// The maximum distance to walk, in meters. Only supported by OTP.
+    max_walk_distance: Option<f64>,
+
+    /// For walking and cycling trips, plan with both OTP and Valhalla and return their
+    /// deduplicated itineraries as one list, ranked by duration. Otherwise only Valhalla is used
+    /// when OTP can't plan the trip.
+    #[serde(default)]
+    merge_backends: bool,
+
+    /// From 0 (avoid hills) to 1 (don't mind hills), for walking and cycling.
+    /// Only supported by Valhalla.
+    use_hills: Option<f64>,
+
+    /// From 0 (avoid ferries) to 1 (prefer ferries). Only supported by Valhalla.
+    use_ferry: Option<f64>,
+
+    /// Stay off highways when driving. Only supported by Valhalla.
+    #[serde(default)]
+    avoid_highways: bool,
+
+    /// The kind of bicycle being ridden, which determines which roads suit it.
+    /// Only supported by Valhalla.
+    bicycle_type: Option<BicycleType>,
+
+    /// The steepest grade to walk, in percent. Only supported by Valhalla.
+    max_grade: Option<u32>,
+
+    /// The fastest to drive, or the average cycling speed, in km/h. Only supported by Valhalla.
+    top_speed: Option<f64>,
+
+    /// Areas to route around, e.g. a closed bridge, as a GeoJSON Polygon or MultiPolygon,
+    /// or as polyline6 encoded rings separated by `,`. Holes in polygons are ignored.
+    /// Only supported by Valhalla, so not for transit trips.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_polygons_from_geojson_or_polyline6"
+    )]
+    avoid_areas: Vec<Polygon>,
+
+    /// Locations to route around, formatted as `lat,lon|lat,lon`.
+    /// Only supported by Valhalla, so not for transit trips.
+    #[serde(default, deserialize_with = "deserialize_points_from_lat_lon_list")]
+    avoid_locations: Vec<Point>,
+
+    /// The language of turn-by-turn instructions, as a tag like `de` or `es-MX`.
+    /// Valhalla supports many languages, our own instructions fall back to English.
+    lang: Option<String>,
+
+    /// Report where `fromPlace` and `toPlace` were snapped onto the street network, in the
+    /// first leg's `fromPlace` and the last leg's `toPlace`.
+    #[serde(default)]
+    report_snapping: bool,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum BicycleType {
+    Road,
+    Hybrid,
+    City,
+    Cross,
+    Mountain,
+}
+
+impl From<BicycleType> for valhalla_api::BicycleType {
+    fn from(value: BicycleType) -> Self {
+        match value {
+            BicycleType::Road => Self::Road,
+            BicycleType::Hybrid => Self::Hybrid,
+            BicycleType::City => Self::City,
+            BicycleType::Cross => Self::Cross,
+            BicycleType::Mountain => Self::Mountain,
+        }
+    }
+}
+
+/// The local time at which a trip should take place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestedTime {
+    /// Local time at the origin
+    DepartAt(chrono::NaiveDateTime),
+    /// Local time at the destination
+    ArriveBy(chrono::NaiveDateTime),
+}
+
+impl RequestedTime {
+    fn local_time(&self) -> chrono::NaiveDateTime {
+        match self {
+            RequestedTime::DepartAt(local_time) | RequestedTime::ArriveBy(local_time) => {
+                *local_time
+            }
+        }
+    }
+
+    fn valhalla_date_time(&self) -> valhalla_api::DateTime {
+        let (r#type, local_time) = match self {
+            RequestedTime::DepartAt(local_time) => {
+                (valhalla_api::DateTimeType::DepartAt, local_time)
+            }
+            RequestedTime::ArriveBy(local_time) => {
+                (valhalla_api::DateTimeType::ArriveBy, local_time)
+            }
+        };
+        valhalla_api::DateTime {
+            r#type,
+            value: local_time.format("%Y-%m-%dT%H:%M").to_string(),
+        }
+    }
+}
+
+impl PlanQuery {
+    /// The OTP request for a trip (or a segment of a trip) from `from_place` to `to_place`.
+    fn otp_plan_request(
+        &self,
+        from_place: Point,
+        to_place: Point,
+        requested_time: Option<RequestedTime>,
+    ) -> crate::Result<otp_api::PlanRequest> {
+        if let Some(walk_speed) = self.walk_speed {
+            if !(walk_speed.is_finite() && walk_speed > 0.0) {
+                return Err(Error::user(format!(
+                    "walkSpeed must be a positive number of meters per second, got: {walk_speed}"
+                )));
+            }
+        }
+        if let Some(max_walk_distance) = self.max_walk_distance {
+            if !(max_walk_distance.is_finite() && max_walk_distance >= 0.0) {
+                return Err(Error::user(format!(
+                    "maxWalkDistance must be a non-negative number of meters, got: {max_walk_distance}"
+                )));
+            }
+        }
+        if self.num_itineraries == 0 {
+            return Err(Error::user("numItineraries must be at least 1"));
+        }
+
+        use otp_api::RequestMode;
+        let mode = match self.mode.transit_access()? {
+            Some(TransitAccess::Walk) => vec![RequestMode::Transit, RequestMode::Walk],
+            Some(TransitAccess::BikeOnBoard) => vec![RequestMode::Transit, RequestMode::Bicycle],
+            Some(TransitAccess::BikeAndRide) => {
+                vec![RequestMode::Transit, RequestMode::BicyclePark]
+            }
+            Some(TransitAccess::ParkAndRide) => vec![RequestMode::Transit, RequestMode::CarPark],
+            None => match self.mode.primary_mode() {
+                None => return Err(Error::user("mode is required")),
+                Some(TravelMode::Walk) => vec![RequestMode::Walk],
+                Some(TravelMode::Bicycle) => vec![RequestMode::Bicycle],
+                Some(TravelMode::Car) => vec![RequestMode::Car],
+                Some(TravelMode::Transit) => unreachable!("transit always has transit_access"),
+            },
+        };
+
+        let local_time = requested_time.map(|requested_time| requested_time.local_time());
+        Ok(otp_api::PlanRequest {
+            from_place: format_lat_lon(from_place),
+            to_place: format_lat_lon(to_place),
+            mode,
+            num_itineraries: self.num_itineraries,
+            date: local_time.map(|local_time| local_time.format("%Y-%m-%d").to_string()),
+            time: local_time.map(|local_time| local_time.format("%H:%M").to_string()),
+            arrive_by: matches!(requested_time, Some(RequestedTime::ArriveBy(_))),
+            wheelchair: self.wheelchair,
+            walk_speed: self.walk_speed,
+            max_walk_distance: self.max_walk_distance,
+        })
+    }
+
+    fn requested_time(&self) -> crate::Result<Option<RequestedTime>> {
+        let (date, time) = match (&self.date, &self.time) {
+            (None, None) => return Ok(None),
+            (Some(date), Some(time)) => (date, time),
+            _ => return Err(Error::user("date and time must be specified together")),
+        };
+
+        let Some(date) = ["%Y-%m-%d", "%m-%d-%Y"]
+            .iter()
+            .find_map(|format| chrono::NaiveDate::parse_from_str(date, format).ok())
+        else {
+            return Err(Error::user(format!(
+                "invalid date: {date:?}, expected YYYY-MM-DD"
+            )));
+        };
+
+        let Some(time) = ["%H:%M", "%H:%M:%S", "%I:%M%P", "%I:%M %P"]
+            .iter()
+            .find_map(|format| chrono::NaiveTime::parse_from_str(time, format).ok())
+        else {
+            return Err(Error::user(format!(
+                "invalid time: {time:?}, expected HH:MM"
+            )));
+        };
+
+        let local_time = date.and_time(time);
+        if self.arrive_by {
+            Ok(Some(RequestedTime::ArriveBy(local_time)))
+        } else {
+            Ok(Some(RequestedTime::DepartAt(local_time)))
+        }
+    }
+
+    fn validate_costing_preferences(&self) -> crate::Result<()> {
+        for (name, value) in [("useHills", self.use_hills), ("useFerry", self.use_ferry)] {
+            if let Some(value) = value {
+                if !(0.0..=1.0).contains(&value) {
+                    return Err(Error::user(format!(
+                        "{name} must be between 0 and 1, got: {value}"
+                    )));
+                }
+            }
+        }
+        if let Some(top_speed) = self.top_speed {
+            if !(top_speed.is_finite() && top_speed > 0.0) {
+                return Err(Error::user(format!(
+                    "topSpeed must be a positive number of km/h, got: {top_speed}"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// The Valhalla request for a trip through `locations`, including any costing preferences
+    /// which apply to `costing`. Preferences for other costings are ignored.
+    fn valhalla_route_query(
+        &self,
+        app_state: &AppState,
+        locations: &[Point],
+        costing: valhalla_api::ModeCosting,
+        num_itineraries: u32,
+        distance_units: DistanceUnit,
+        date_time: Option<valhalla_api::DateTime>,
+    ) -> crate::Result<valhalla_api::ValhallaRouteQuery> {
+        self.validate_costing_preferences()?;
+
+        use valhalla_api::ModeCosting;
+        let costing_options = match costing {
+            ModeCosting::Auto => valhalla_api::CostingOptions {
+                use_ferry: self.use_ferry,
+                use_highways: self.avoid_highways.then_some(0.0),
+                top_speed: self.top_speed,
+                ..Default::default()
+            },
+            ModeCosting::Bicycle => valhalla_api::CostingOptions {
+                use_hills: self.use_hills,
+                use_ferry: self.use_ferry,
+                bicycle_type: self.bicycle_type.map(Into::into),
+                cycling_speed: self.top_speed,
+                ..Default::default()
+            },
+            ModeCosting::Pedestrian => valhalla_api::CostingOptions {
+                use_hills: self.use_hills,
+                use_ferry: self.use_ferry,
+                max_grade: self.max_grade,
+                r#type: self
+                    .wheelchair
+                    .then_some(valhalla_api::PedestrianType::Wheelchair),
+                ..Default::default()
+            },
+        };
+
+        let mut route_query = app_state.valhalla_router().route_query(
+            locations,
+            costing,
+            num_itineraries,
+            distance_units,
+            date_time,
+        );
+        if !costing_options.is_empty() {
+            route_query.costing_options.insert(costing, costing_options);
+        }
+        route_query.exclude_polygons = self
+            .avoid_areas
+            .iter()
+            .map(|polygon| polygon.exterior().coords().map(|c| [c.x, c.y]).collect())
+            .collect();
+        route_query.exclude_locations = self
+            .avoid_locations
+            .iter()
+            .copied()
+            .map(LonLat::from)
+            .collect();
+        route_query.language = self.lang.clone();
+        Ok(route_query)
+    }
+
+    fn language(&self) -> Language {
+        self.lang
+            .as_deref()
+            .map(Language::from_tag)
+            .unwrap_or_default()
+    }
+
+    /// Whether the trip must route around some areas or locations, which only Valhalla supports.
+    fn has_avoids(&self) -> bool {
+        !self.avoid_areas.is_empty() || !self.avoid_locations.is_empty()
+    }
+
+    /// Every location the trip visits, in order: origin, intermediate stops, and destination.
+    fn waypoints(&self) -> Vec<Point> {
+        let mut waypoints = Vec::with_capacity(self.via.len() + 2);
+        waypoints.push(self.from_place);
+        waypoints.extend(&self.via);
+        waypoints.push(self.to_place);
+        waypoints
+    }
 }
 
 #[derive(Debug, Serialize, Clone, PartialEq)]
@@ -56,6 +392,8 @@ pub struct Plan {
 #[serde(rename_all = "camelCase")]
 pub struct Itinerary {
     mode: TravelMode,
+    /// Which backend planned this itinerary
+    source: ItinerarySource,
     /// seconds
     pub(crate) duration: f64,
     /// unix millis, UTC
@@ -72,7 +410,20 @@ pub struct Itinerary {
     pub(crate) legs: Vec<Leg>,
 }
 
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ItinerarySource {
+    Otp,
+    Valhalla,
+    /// Best-effort: OTP planned the start of the trip, and Valhalla planned the rest.
+    Stitched,
+}
+
 impl Itinerary {
+    /// Itineraries whose geometries never stray further than this from one another are
+    /// considered to be the same route.
+    const SIMILAR_ROUTE_METERS: f64 = 30.0;
+
     pub fn distance_meters(&self) -> f64 {
         convert_to_meters(self.distance, self.distance_units)
     }
@@ -85,37 +436,82 @@ impl Itinerary {
         combined_geometry
     }
 
-    pub fn from_valhalla(valhalla: &valhalla_api::Trip, mode: TravelMode) -> Self {
+    /// Whether `self` and `other` follow nearly the same path, as measured by the Fréchet distance
+    /// between their geometries.
+    pub(crate) fn is_similar_to(&self, other: &Itinerary) -> bool {
+        use geo::line_measures::FrechetDistance;
+        use geo::{Haversine, Simplify};
+
+        // Comparing every pair of coordinates gets expensive for long routes, so first discard
+        // detail far finer than our threshold. Epsilon is in degrees, roughly 1 meter.
+        let epsilon = 0.00001;
+        let geometry = self.combined_geometry().simplify(&epsilon);
+        let other_geometry = other.combined_geometry().simplify(&epsilon);
+        if geometry.0.is_empty() || other_geometry.0.is_empty() {
+            return false;
+        }
+        Haversine.frechet_distance(&geometry, &other_geometry) < Self::SIMILAR_ROUTE_METERS
+    }
+
+    /// Continue this itinerary with `connection`, departing as soon as this itinerary arrives.
+    pub(crate) fn stitch(&mut self, connection: &Itinerary) {
+        let arrival = self.end_time;
+        let connection_start = connection.start_time;
+        let shift =
+            |time: SystemTime| arrival + time.duration_since(connection_start).unwrap_or_default();
+
+        for leg in &connection.legs {
+            let mut leg = leg.clone();
+            leg.start_time = shift(leg.start_time);
+            leg.end_time = shift(leg.end_time);
+            self.legs.push(leg);
+        }
+        self.end_time = shift(connection.end_time);
+        self.duration = self
+            .end_time
+            .duration_since(self.start_time)
+            .unwrap_or_default()
+            .as_secs_f64();
+        self.distance += convert_from_meters(connection.distance_meters(), self.distance_units);
+        extend_bounds(&mut self.bounds, &connection.bounds);
+        self.source = ItinerarySource::Stitched;
+    }
+
+    pub fn from_valhalla(
+        valhalla: &valhalla_api::Trip,
+        mode: TravelMode,
+        requested_time: Option<RequestedTime>,
+    ) -> Self {
         let bounds = Rect::new(
             geo::coord!(x: valhalla.summary.min_lon, y: valhalla.summary.min_lat),
             geo::coord!(x: valhalla.summary.max_lon, y: valhalla.summary.max_lat),
         );
 
-        let start_time = SystemTime::now();
+        let start_time = valhalla_trip_start_time(valhalla, requested_time);
         let end_time = start_time + Duration::from_millis((valhalla.summary.time * 1000.0) as u64);
         debug_assert!(
             valhalla.locations.len() == valhalla.legs.len() + 1,
             "assuming each leg has a start and end location"
         );
 
-        let mut start_time = SystemTime::now();
+        let mut leg_start_time = start_time;
         let legs = valhalla
             .legs
             .iter()
             .zip(valhalla.locations.windows(2))
             .map(|(v_leg, locations)| {
-                let leg_start_time = start_time;
                 let leg_end_time =
-                    start_time + Duration::from_millis((v_leg.summary.time * 1000.0) as u64);
-                start_time = leg_end_time;
-                Leg::from_valhalla(
+                    leg_start_time + Duration::from_millis((v_leg.summary.time * 1000.0) as u64);
+                let leg = Leg::from_valhalla(
                     v_leg,
                     mode,
                     leg_start_time,
                     leg_end_time,
-                    locations[0],
-                    locations[1],
-                )
+                    (&locations[0]).into(),
+                    (&locations[1]).into(),
+                );
+                leg_start_time = leg_end_time;
+                leg
             })
             .collect();
 
@@ -127,6 +523,7 @@ impl Itinerary {
             distance: valhalla.summary.length,
             bounds,
             distance_units: valhalla.units,
+            source: ItinerarySource::Valhalla,
             legs,
         }
     }
@@ -135,6 +532,7 @@ impl Itinerary {
         itinerary: &otp_api::Itinerary,
         mode: TravelMode,
         distance_unit: DistanceUnit,
+        language: Language,
     ) -> crate::Result<Self> {
         // OTP responses are always in meters
         let distance_meters: f64 = itinerary.legs.iter().map(|l| l.distance).sum();
@@ -144,7 +542,7 @@ impl Itinerary {
             .enumerate()
             .map(|(idx, leg)| {
                 let is_destination_leg = idx == itinerary.legs.len() - 1;
-                Leg::from_otp(leg, is_destination_leg, distance_unit)
+                Leg::from_otp(leg, is_destination_leg, distance_unit, language)
             })
             .collect()
         else {
@@ -172,18 +570,55 @@ impl Itinerary {
             mode,
             distance: convert_from_meters(distance_meters, distance_unit),
             distance_units: distance_unit,
+            source: ItinerarySource::Otp,
             bounds: itinerary_bounds,
             legs,
         })
     }
 }
 
+/// Valhalla reports times as local times without a date, so we resolve the absolute start time
+/// of the trip from the requested local time and the time zone Valhalla reports for the trip.
+fn valhalla_trip_start_time(
+    trip: &valhalla_api::Trip,
+    requested_time: Option<RequestedTime>,
+) -> SystemTime {
+    let (local_time, location) = match requested_time {
+        None => return SystemTime::now(),
+        Some(RequestedTime::DepartAt(local_time)) => (local_time, trip.locations.first()),
+        Some(RequestedTime::ArriveBy(local_time)) => (local_time, trip.locations.last()),
+    };
+
+    let time_zone_offset = location
+        .and_then(|location| location.time_zone_offset.as_ref())
+        .and_then(|offset| offset.parse::<chrono::FixedOffset>().ok())
+        .unwrap_or_else(|| {
+            log::warn!("valhalla trip is missing a valid time_zone_offset, assuming UTC");
+            chrono::FixedOffset::east_opt(0).expect("valid offset")
+        });
+    let requested_instant: SystemTime = local_time
+        .and_local_timezone(time_zone_offset)
+        .single()
+        .expect("fixed offsets are never ambiguous")
+        .into();
+
+    match requested_time {
+        Some(RequestedTime::ArriveBy(_)) => {
+            requested_instant - Duration::from_millis((trip.summary.time * 1000.0) as u64)
+        }
+        _ => requested_instant,
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
 struct Place {
     #[serde(flatten)]
     location: LonLat,
     name: Option<String>,
+    /// Only reported for the trip's endpoints, when requested with `reportSnapping`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    snap: Option<Snap>,
 }
 
 impl From<&otp_api::Place> for Place {
@@ -191,6 +626,7 @@ impl From<&otp_api::Place> for Place {
         Self {
             location: value.location.into(),
             name: value.name.clone(),
+            snap: None,
         }
     }
 }
@@ -200,6 +636,7 @@ impl From<valhalla_api::LonLat> for Place {
         Self {
             location: value,
             name: None,
+            snap: None,
         }
     }
 }
@@ -322,6 +759,9 @@ pub struct Maneuver {
     // pub travel_mode: String,
     // pub travel_type: String,
     pub r#type: ManeuverType,
+    /// Set for maneuvers which some travelers can't take, like entering stairs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub accessibility_flag: Option<AccessibilityFlag>,
     pub verbal_post_transition_instruction: Option<String>,
     pub start_point: LonLat,
     pub bearing_before: u16,
@@ -330,6 +770,24 @@ pub struct Maneuver {
     // pub verbal_succinct_transition_instruction: Option<String>,
 }
 
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum AccessibilityFlag {
+    Steps,
+    /// Elevators are accessible, but may be out of service
+    Elevator,
+}
+
+impl AccessibilityFlag {
+    fn for_maneuver_type(maneuver_type: ManeuverType) -> Option<Self> {
+        match maneuver_type {
+            ManeuverType::StepsEnter => Some(Self::Steps),
+            ManeuverType::ElevatorEnter => Some(Self::Elevator),
+            _ => None,
+        }
+    }
+}
+
 impl Maneuver {
     fn from_valhalla(
         valhalla: valhalla_api::Maneuver,
@@ -351,6 +809,7 @@ impl Maneuver {
             street_names: valhalla.street_names,
             duration_seconds: valhalla.time,
             r#type: valhalla.r#type,
+            accessibility_flag: AccessibilityFlag::for_maneuver_type(valhalla.r#type),
             start_point: Point(leg_geometry[valhalla.begin_shape_index as usize]).into(),
             verbal_post_transition_instruction: valhalla.verbal_post_transition_instruction,
             distance: valhalla.length,
@@ -364,18 +823,15 @@ impl Maneuver {
         otp: otp_api::Step,
         geometry: LineString,
         prev_geometry: Option<&LineString>,
+        previous_steps: &[otp_api::Step],
         leg: &otp_api::Leg,
         distance_unit: DistanceUnit,
+        language: Language,
     ) -> Self {
-        let instruction = maneuver_instruction(
-            leg.mode,
-            otp.relative_direction,
-            otp.absolute_direction,
-            &otp.street_name,
-        );
+        let instruction = maneuver_instruction(leg.mode, &otp, previous_steps, language);
 
         let verbal_post_transition_instruction =
-            build_verbal_post_transition_instruction(otp.distance, distance_unit);
+            build_verbal_post_transition_instruction(otp.distance, distance_unit, language);
 
         let street_names = if let Some(true) = otp.bogus_name {
             None
@@ -388,9 +844,11 @@ impl Maneuver {
             .unwrap_or(bearing_after);
 
         let duration_seconds = otp.distance / leg.distance * leg.duration_seconds();
+        let r#type = otp.relative_direction.into();
         Self {
             instruction,
-            r#type: otp.relative_direction.into(),
+            r#type,
+            accessibility_flag: AccessibilityFlag::for_maneuver_type(r#type),
             street_names,
             verbal_post_transition_instruction,
             distance: convert_from_meters(otp.distance, distance_unit),
@@ -407,78 +865,98 @@ impl Maneuver {
     }
 }
 
-/// Returns the natural language description of the maneuver.
-// We could do so much better. Look at Valhalla's Odin.
-//
-// e.g. take context of previous maneuver. "Bear right to stay on Main Street"
-// TODO: localize
+/// Returns the natural language description of the maneuver, in the context of the steps
+/// which came before it in the leg.
+// Valhalla's Odin does much more, e.g. merging short steps, and describing landmarks.
 fn maneuver_instruction(
     mode: otp_api::TransitMode,
-    maneuver_type: otp_api::RelativeDirection,
-    absolute_direction: Option<otp_api::AbsoluteDirection>,
-    street_name: &str,
+    step: &otp_api::Step,
+    previous_steps: &[otp_api::Step],
+    language: Language,
 ) -> Option<String> {
-    match maneuver_type {
-        otp_api::RelativeDirection::Depart => {
-            if let Some(absolute_direction) = absolute_direction {
+    use crate::util::i18n::{Compass, Travel, Turn};
+    use otp_api::RelativeDirection;
+
+    // Names generated by OTP, like "path" or "sidewalk", are only worth mentioning once.
+    let is_bogus_name = step.bogus_name == Some(true);
+    let street_name = if is_bogus_name
+        && previous_steps
+            .iter()
+            .any(|previous| previous.street_name == step.street_name)
+    {
+        None
+    } else {
+        Some(step.street_name.as_str())
+    };
+    let stay_on = step.stay_on == Some(true);
+    let exit = step.exit.as_deref().filter(|exit| !exit.is_empty());
+
+    let turn = |turn: Turn| match exit {
+        Some(exit) => language.take_exit(exit, street_name),
+        None => language.turn(turn, street_name, stay_on),
+    };
+
+    let instruction = match step.relative_direction {
+        RelativeDirection::Depart => {
+            if let Some(absolute_direction) = &step.absolute_direction {
                 let direction = match absolute_direction {
-                    otp_api::AbsoluteDirection::North => "north",
-                    otp_api::AbsoluteDirection::Northeast => "northeast",
-                    otp_api::AbsoluteDirection::East => "east",
-                    otp_api::AbsoluteDirection::Southeast => "southeast",
-                    otp_api::AbsoluteDirection::South => "south",
-                    otp_api::AbsoluteDirection::Southwest => "southwest",
-                    otp_api::AbsoluteDirection::West => "west",
-                    otp_api::AbsoluteDirection::Northwest => "northwest",
+                    otp_api::AbsoluteDirection::North => Compass::North,
+                    otp_api::AbsoluteDirection::Northeast => Compass::Northeast,
+                    otp_api::AbsoluteDirection::East => Compass::East,
+                    otp_api::AbsoluteDirection::Southeast => Compass::Southeast,
+                    otp_api::AbsoluteDirection::South => Compass::South,
+                    otp_api::AbsoluteDirection::Southwest => Compass::Southwest,
+                    otp_api::AbsoluteDirection::West => Compass::West,
+                    otp_api::AbsoluteDirection::Northwest => Compass::Northwest,
                 };
-                let mode = match mode {
-                    otp_api::TransitMode::Walk => "Walk",
-                    otp_api::TransitMode::Bicycle => "Bike",
-                    otp_api::TransitMode::Car => "Drive",
-                    _ => "Transit",
+                let travel = match mode {
+                    otp_api::TransitMode::Walk => Travel::Walk,
+                    otp_api::TransitMode::Bicycle => Travel::Bike,
+                    otp_api::TransitMode::Car => Travel::Drive,
+                    _ => Travel::Transit,
                 };
-                Some(format!("{mode} {direction} on {street_name}."))
+                language.depart(travel, direction, street_name)
             } else {
-                Some("Depart.".to_string())
+                language.depart_without_direction()
             }
         }
-        otp_api::RelativeDirection::HardLeft => {
-            Some(format!("Turn sharp left onto {street_name}."))
-        }
-        otp_api::RelativeDirection::Left => Some(format!("Turn left onto {street_name}.")),
-        otp_api::RelativeDirection::SlightlyLeft => {
-            Some(format!("Turn slightly left onto {street_name}."))
-        }
-        otp_api::RelativeDirection::Continue => Some(format!("Continue onto {street_name}.")),
-        otp_api::RelativeDirection::SlightlyRight => {
-            Some(format!("Turn slightly right onto {street_name}."))
-        }
-        otp_api::RelativeDirection::Right => Some(format!("Turn right onto {street_name}.")),
-        otp_api::RelativeDirection::HardRight => {
-            Some(format!("Turn sharp right onto {street_name}."))
-        }
-        otp_api::RelativeDirection::CircleClockwise
-        | otp_api::RelativeDirection::CircleCounterclockwise => {
-            Some("Enter the roundabout.".to_string())
-        }
-        otp_api::RelativeDirection::Elevator => Some("Enter the elevator.".to_string()),
-        otp_api::RelativeDirection::UturnLeft | otp_api::RelativeDirection::UturnRight => {
-            Some("Make a U-turn.".to_string())
+        RelativeDirection::CircleClockwise | RelativeDirection::CircleCounterclockwise => {
+            match exit {
+                Some(exit) => language.take_roundabout_exit(exit),
+                None => language.enter_roundabout(),
+            }
         }
-    }
+        RelativeDirection::Elevator => language.enter_elevator(),
+        RelativeDirection::UturnLeft | RelativeDirection::UturnRight => language.make_u_turn(),
+        // Open areas have no street to turn onto, and their generated names read poorly
+        _ if step.area == Some(true) => language.cross(street_name.filter(|_| !is_bogus_name)),
+        RelativeDirection::HardLeft => turn(Turn::SharpLeft),
+        RelativeDirection::Left => turn(Turn::Left),
+        RelativeDirection::SlightlyLeft => turn(Turn::SlightLeft),
+        RelativeDirection::Continue => match exit {
+            Some(exit) => language.take_exit(exit, street_name),
+            None => language.continue_onto(street_name, stay_on),
+        },
+        RelativeDirection::SlightlyRight => turn(Turn::SlightRight),
+        RelativeDirection::Right => turn(Turn::Right),
+        RelativeDirection::HardRight => turn(Turn::SharpRight),
+    };
+    Some(instruction)
 }
 
 fn build_verbal_post_transition_instruction(
     distance: f64,
     distance_unit: DistanceUnit,
+    language: Language,
 ) -> Option<String> {
     if distance == 0.0 {
         None
     } else {
-        Some(format!(
-            "Continue for {}.",
-            format_meters(distance, distance_unit.measurement_system())
-        ))
+        Some(language.continue_for(&format_meters(
+            distance,
+            distance_unit.measurement_system(),
+            language,
+        )))
     }
 }
 
@@ -499,6 +977,7 @@ impl Leg {
         otp: &otp_api::Leg,
         is_destination_leg: bool,
         distance_unit: DistanceUnit,
+        language: Language,
     ) -> std::result::Result<Self, PolylineError> {
         debug_assert_ne!(Self::OTP_GEOMETRY_PRECISION, Self::GEOMETRY_PRECISION);
         let geometry = decode_polyline(&otp.leg_geometry.points, Self::OTP_GEOMETRY_PRECISION)?;
@@ -511,7 +990,7 @@ impl Leg {
             | otp_api::TransitMode::Bicycle
             | otp_api::TransitMode::Car => {
                 let mut maneuvers = Vec::with_capacity(otp.steps.len() + 1);
-                for otp_step in otp.steps.iter().cloned() {
+                for (idx, otp_step) in otp.steps.iter().cloned().enumerate() {
                     let step_geometry =
                         segmenter
                             .next_segment(otp_step.distance)
@@ -527,8 +1006,10 @@ impl Leg {
                         otp_step,
                         step_geometry,
                         prev_step_geometry,
+                        &otp.steps[..idx],
                         otp,
                         distance_unit,
+                        language,
                     );
                     maneuvers.push(next);
                 }
@@ -541,11 +1022,12 @@ impl Leg {
                         .and_then(|maneuver| bearing_at_end(&maneuver.geometry))
                         .unwrap_or(bearing_after);
                     let maneuver = Maneuver {
-                        instruction: Some("Arrive at your destination.".to_string()),
+                        instruction: Some(language.arrive()),
                         distance: 0.0,
                         street_names: None,
                         duration_seconds: 0.0,
                         r#type: ManeuverType::Destination,
+                        accessibility_flag: None,
                         verbal_post_transition_instruction: None,
                         start_point: to_place.location,
                         geometry: LineString::new(vec![to_place.location.into()]),
@@ -625,64 +1107,205 @@ impl actix_web::Responder for PlanResponseOk {
 #[get("/v6/plan")]
 pub async fn get_plan(
     query: web::Query<PlanQuery>,
-    req: HttpRequest,
+    _req: HttpRequest,
     app_state: web::Data<AppState>,
 ) -> std::result::Result<PlanResponseOk, PlanResponseErr> {
-    _get_plan(query, req, app_state).await
+    _get_plan(query, app_state).await
 }
 
 pub async fn _get_plan(
     query: web::Query<PlanQuery>,
-    req: HttpRequest,
     app_state: web::Data<AppState>,
 ) -> std::result::Result<PlanResponseOk, PlanResponseErr> {
-    let Some(primary_mode) = query.mode.first() else {
+    // Locating the endpoints doesn't depend on the plan, so neither waits on the other
+    let snapping = async {
+        if query.report_snapping {
+            fetch_endpoint_snaps(&query, &app_state).await
+        } else {
+            None
+        }
+    };
+    let (result, snapping) =
+        futures_util::future::join(plan_trip(&query, &app_state), snapping).await;
+    let Some(snapping) = snapping else {
+        return result;
+    };
+
+    match result {
+        Ok(mut response) => {
+            for itinerary in &mut response.plan.itineraries {
+                if let Some(first_leg) = itinerary.legs.first_mut() {
+                    first_leg.from_place.snap = snapping.from_place.clone();
+                }
+                if let Some(last_leg) = itinerary.legs.last_mut() {
+                    last_leg.to_place.snap = snapping.to_place.clone();
+                }
+            }
+            Ok(response)
+        }
+        Err(mut e) => {
+            // e.g. a pin dropped in a lake, which is far from any street
+            if e.is_no_path() {
+                e.error.snapping = Some(snapping);
+            }
+            Err(e)
+        }
+    }
+}
+
+async fn plan_trip(
+    query: &web::Query<PlanQuery>,
+    app_state: &web::Data<AppState>,
+) -> std::result::Result<PlanResponseOk, PlanResponseErr> {
+    let Some(primary_mode) = query.mode.primary_mode() else {
         return Err(PlanResponseErr::from(Error::user("mode is required")));
     };
+    let primary_mode = &primary_mode;
 
     let distance_units = query
         .preferred_distance_units
         .unwrap_or(DistanceUnit::Kilometers);
 
-    // TODO: Handle bus+bike if bike is first, for now all our clients are responsible for enforcing that
-    // the "primary" mode appears first.
-    match primary_mode {
-        TravelMode::Transit => otp_plan(&query, req, &app_state, primary_mode).await,
+    // Validate up front, so that bad input is reported consistently for every mode
+    query.requested_time()?;
+    query.mode.transit_access()?;
+    query.validate_costing_preferences()?;
+    if query.has_avoids() && primary_mode == &TravelMode::Transit {
+        return Err(PlanResponseErr::from(Error::user(
+            "avoidAreas and avoidLocations are not supported for transit trips",
+        )));
+    }
+
+    let result = match primary_mode {
+        TravelMode::Transit => otp_plan(query, app_state, primary_mode).await,
+        // OTP can't route around areas, so don't give it the chance to ignore them
+        TravelMode::Bicycle | TravelMode::Walk if query.has_avoids() => {
+            valhalla_plan(query, app_state, primary_mode, distance_units, primary_mode).await
+        }
+        TravelMode::Bicycle | TravelMode::Walk if query.merge_backends => {
+            let (otp_result, valhalla_result) = futures_util::future::join(
+                otp_plan(query, app_state, primary_mode),
+                valhalla_plan(query, app_state, primary_mode, distance_units, primary_mode),
+            )
+            .await;
+            match (otp_result, valhalla_result) {
+                (Ok(otp_response), Ok(valhalla_response)) => Ok(PlanResponseOk::merge(
+                    otp_response,
+                    valhalla_response,
+                    query.num_itineraries as usize,
+                )),
+                (Ok(otp_response), Err(e)) => {
+                    log::error!("Valhalla failed to plan {primary_mode:?} route: {e}");
+                    Ok(otp_response)
+                }
+                (Err(e), valhalla_result) => {
+                    log_otp_fallback(&e, primary_mode);
+                    valhalla_result
+                }
+            }
+        }
+        TravelMode::Bicycle | TravelMode::Walk
+            if app_state.upstream_config().race_otp_and_valhalla =>
+        {
+            use futures_util::future::{select, Either};
+            let otp_future = std::pin::pin!(otp_plan(query, app_state, primary_mode));
+            let valhalla_future = std::pin::pin!(valhalla_plan(
+                query,
+                app_state,
+                primary_mode,
+                distance_units,
+                primary_mode
+            ));
+            match select(otp_future, valhalla_future).await {
+                Either::Left((Ok(otp_response), _valhalla_future)) => Ok(otp_response),
+                Either::Left((Err(e), valhalla_future)) => {
+                    log_otp_fallback(&e, primary_mode);
+                    valhalla_future.await
+                }
+                Either::Right((valhalla_result, otp_future)) => {
+                    // Even though Valhalla finished first, we still prefer OTP's results if they
+                    // arrive shortly after. If Valhalla failed, OTP is all we have left to wait on.
+                    let grace = if valhalla_result.is_ok() {
+                        app_state.upstream_config().otp_race_grace
+                    } else {
+                        app_state.upstream_config().otp_timeout
+                    };
+                    match actix_web::rt::time::timeout(grace, otp_future).await {
+                        Ok(Ok(otp_response)) => Ok(otp_response),
+                        Ok(Err(e)) => {
+                            log_otp_fallback(&e, primary_mode);
+                            valhalla_result
+                        }
+                        Err(_elapsed) => {
+                            log::info!(
+                                "OTP didn't plan {primary_mode:?} route within {grace:?} of Valhalla"
+                            );
+                            valhalla_result
+                        }
+                    }
+                }
+            }
+        }
         other => {
             if primary_mode == &TravelMode::Bicycle || primary_mode == &TravelMode::Walk {
-                match otp_plan(&query, req, &app_state, primary_mode).await {
+                match otp_plan(query, app_state, primary_mode).await {
                     Ok(otp_response) => {
-                        debug_assert_eq!(
-                            1,
-                            otp_response.plan.itineraries.len(),
-                            "expected exactly one itinerary from OTP"
-                        );
                         // Prefer OTP response when available - anecdotally, it tends to be higher quality than Valhalla routes for
                         // walking and cycling.
                         //
-                        // We could combine the results and return them all, but I seemingly never want the valhalla directions when OTP are available.
+                        // Clients can opt in to combined results with `mergeBackends`, but I seemingly never want the valhalla directions when OTP are available.
                         //
                         // Plus, when re-routing, the navigation SDK tries to do route-matching so that the "most similar" route
                         // will be applied. The end result is that you sometimes end up on the valhalla route, which IME is typically worse.
                         return Ok(otp_response);
                     }
-                    Err(e) => {
-                        // match error_code to raw value of ErrorType enum
-                        match ErrorType::try_from(e.error.error_code) {
-                            Ok(ErrorType::NoCoverageForArea) => {
-                                log::debug!("No OTP coverage for route");
-                            }
-                            other => {
-                                debug_assert!(other.is_ok(), "unexpected error code: {e:?}");
-                                // We're mixing with results from Valhalla anyway, so don't surface this error
-                                // to the user. Likely we just don't support this area.
-                                log::error!("OTP failed to plan {primary_mode:?} route: {e}");
-                            }
-                        }
-                    }
+                    Err(e) => log_otp_fallback(&e, primary_mode),
                 }
             }
-            Ok(valhalla_plan(&query, &app_state, primary_mode, distance_units, other).await?)
+            valhalla_plan(query, app_state, primary_mode, distance_units, other).await
+        }
+    };
+
+    result.map_err(|mut e| {
+        if query.wheelchair && e.is_no_path() {
+            e.error.message = "No wheelchair accessible route was found.".to_string();
+        }
+        e
+    })
+}
+
+/// Where the trip's endpoints are snapped onto the street network. This is only informational,
+/// so a failure to locate them doesn't fail the plan.
+async fn fetch_endpoint_snaps(query: &PlanQuery, app_state: &AppState) -> Option<EndpointSnaps> {
+    let mode = query.mode.primary_mode()?;
+    match fetch_snaps(app_state, &[query.from_place, query.to_place], mode).await {
+        Ok(snaps) => {
+            let [from_place, to_place] =
+                <[Option<Snap>; 2]>::try_from(snaps).expect("one snap per place");
+            Some(EndpointSnaps {
+                from_place,
+                to_place,
+            })
+        }
+        Err(e) => {
+            log::warn!("failed to locate trip endpoints: {e}");
+            None
+        }
+    }
+}
+
+/// Logs why OTP couldn't plan a trip which we're about to plan with Valhalla instead.
+fn log_otp_fallback(e: &PlanResponseErr, primary_mode: &TravelMode) {
+    // match error_code to raw value of ErrorType enum
+    match ErrorType::try_from(e.error.error_code) {
+        Ok(ErrorType::NoCoverageForArea) => {
+            log::debug!("No OTP coverage for route");
+        }
+        other => {
+            debug_assert!(other.is_ok(), "unexpected error code: {e:?}");
+            // We're mixing with results from Valhalla anyway, so don't surface this error
+            // to the user. Likely we just don't support this area.
+            log::error!("OTP failed to plan {primary_mode:?} route: {e}");
         }
     }
 }
@@ -702,19 +1325,106 @@ async fn valhalla_plan(
         TravelMode::Car => valhalla_api::ModeCosting::Auto,
         TravelMode::Walk => valhalla_api::ModeCosting::Pedestrian,
     };
+    let requested_time = query.requested_time()?;
 
-    // route?json={%22locations%22:[{%22lat%22:47.575837,%22lon%22:-122.339414},{%22lat%22:47.651048,%22lon%22:-122.347234}],%22costing%22:%22auto%22,%22alternates%22:3,%22units%22:%22miles%22}
-    let router_url = app_state.valhalla_router().plan_url(
-        query.from_place,
-        query.to_place,
+    let route_query = query.valhalla_route_query(
+        app_state,
+        &query.waypoints(),
         mode,
         query.num_itineraries,
         distance_units,
+        requested_time.map(|requested_time| requested_time.valhalla_date_time()),
     )?;
-    let valhalla_response: reqwest::Response = reqwest::get(router_url).await.map_err(|e| {
-        log::error!("error while fetching from valhalla service: {e}");
-        PlanResponseErr::from(Error::server(e))
-    })?;
+    let valhalla_route_response = fetch_valhalla_route(app_state, &route_query).await?;
+
+    Ok(PlanResponseOk::from_valhalla(
+        *primary_mode,
+        valhalla_route_response,
+        requested_time,
+    )?)
+}
+
+async fn otp_plan(
+    query: &web::Query<PlanQuery>,
+    app_state: &web::Data<AppState>,
+    primary_mode: &TravelMode,
+) -> Result<PlanResponseOk, PlanResponseErr> {
+    let waypoints = query.waypoints();
+    let requested_time = query.requested_time()?;
+    if query.arrive_by && !query.via.is_empty() {
+        return Err(
+            Error::user("arriveBy is not supported for trips with intermediate stops").into(),
+        );
+    }
+    // Validate before looking for a router, so that bad input is reported as such
+    query.otp_plan_request(waypoints[0], waypoints[1], requested_time)?;
+
+    let plan_endpoints = app_state
+        .otp_cluster()
+        .find_plan_endpoints_for_waypoints(&waypoints);
+    let distance_units = query
+        .preferred_distance_units
+        .unwrap_or(DistanceUnit::Kilometers);
+    if plan_endpoints.is_empty() {
+        if *primary_mode == TravelMode::Transit {
+            if let Some(stitched_result) =
+                stitched_cross_router_plan(query, app_state, requested_time, distance_units).await
+            {
+                return stitched_result;
+            }
+        }
+        let mut error = PlanResponseErr::from(
+            Error::user("Transit directions not available for this area.")
+                .error_type(ErrorType::NoCoverageForArea),
+        );
+        error.error.coverage =
+            CoverageHint::nearest(&app_state.otp_cluster(), query.from_place, query.to_place);
+        return Err(error);
+    }
+
+    let mut plan_endpoints = plan_endpoints.into_iter().peekable();
+    while let Some(plan_endpoint) = plan_endpoints.next() {
+        let otp_plan_response =
+            otp_plan_with_router(query, app_state, &plan_endpoint, requested_time).await?;
+        let is_no_path = otp_plan_response
+            .error
+            .as_ref()
+            .is_some_and(otp_api::PlanError::is_no_path);
+        if is_no_path
+            && app_state.upstream_config().fall_back_to_next_otp_router
+            && plan_endpoints.peek().is_some()
+        {
+            log::info!(
+                "no path found by {}, trying the next router",
+                plan_endpoint.url
+            );
+            continue;
+        }
+        return PlanResponseOk::from_otp(
+            *primary_mode,
+            otp_plan_response,
+            distance_units,
+            query.language(),
+        );
+    }
+    unreachable!("there's at least one router")
+}
+
+pub(super) async fn fetch_valhalla_route(
+    app_state: &AppState,
+    route_query: &valhalla_api::ValhallaRouteQuery,
+) -> Result<valhalla_api::ValhallaRouteResponseResult, PlanResponseErr> {
+    let valhalla_response: reqwest::Response = app_state
+        .http_client()
+        .post(app_state.valhalla_router().route_url())
+        .json(route_query)
+        .timeout(app_state.upstream_config().valhalla_timeout)
+        .send()
+        .await
+        .map_err(|e| {
+            log::error!("error while fetching from valhalla service: {e}");
+            PlanResponseErr::from(Error::server(e))
+        })?;
     if !valhalla_response.status().is_success() {
         log::warn!(
             "upstream HTTP Error from valhalla service: {}",
@@ -737,43 +1447,228 @@ async fn valhalla_plan(
     );
     response.content_type("application/json;charset=utf-8");
 
-    let valhalla_route_response: valhalla_api::ValhallaRouteResponseResult =
-        valhalla_response.json().await.map_err(|e| {
-            log::error!("error while parsing valhalla response: {e}");
-            PlanResponseErr::from(Error::server(e))
-        })?;
+    valhalla_response.json().await.map_err(|e| {
+        log::error!("error while parsing valhalla response: {e}");
+        PlanResponseErr::from(Error::server(e))
+    })
+}
 
-    Ok(PlanResponseOk::from_valhalla(
-        *primary_mode,
-        valhalla_route_response,
-    )?)
+/// Plan a transit trip whose destination is covered by a different OTP router than its origin.
+///
+/// There's no single transit network for the whole trip, so as a best effort we take transit
+/// to a hub at the edge of the origin's router, and then walk or drive the rest of the way.
+/// Returns `None` if the trip doesn't span routers, or they're too far apart to connect.
+async fn stitched_cross_router_plan(
+    query: &PlanQuery,
+    app_state: &AppState,
+    requested_time: Option<RequestedTime>,
+    distance_units: DistanceUnit,
+) -> Option<Result<PlanResponseOk, PlanResponseErr>> {
+    // Further than this, driving the rest of the way is more realistic than walking.
+    const MAX_WALKING_CONNECTION_METERS: f64 = 2000.0;
+
+    if !query.via.is_empty() || matches!(requested_time, Some(RequestedTime::ArriveBy(_))) {
+        // We'd need to know when to leave the hub before planning how to get there.
+        return None;
+    }
+    let (plan_endpoint, hub) = app_state.otp_cluster().find_cross_router_hub(
+        query.from_place,
+        query.to_place,
+        app_state.upstream_config().max_cross_router_gap_meters,
+    )?;
+    log::info!("stitching cross-router trip via hub {hub:?}");
+
+    let result = async {
+        let plan_request = query.otp_plan_request(query.from_place, hub, requested_time)?;
+        let otp_response = PlanResponseOk::from_otp(
+            TravelMode::Transit,
+            fetch_otp_plan(&plan_endpoint, &plan_request, app_state).await?,
+            distance_units,
+            query.language(),
+        )?;
+
+        use geo::{Distance, Haversine};
+        let connection_mode = match query.mode.transit_access()? {
+            Some(TransitAccess::BikeOnBoard) => TravelMode::Bicycle,
+            _ if Haversine.distance(hub, query.to_place) <= MAX_WALKING_CONNECTION_METERS => {
+                TravelMode::Walk
+            }
+            _ => TravelMode::Car,
+        };
+        let costing = match connection_mode {
+            TravelMode::Bicycle => valhalla_api::ModeCosting::Bicycle,
+            TravelMode::Walk => valhalla_api::ModeCosting::Pedestrian,
+            _ => valhalla_api::ModeCosting::Auto,
+        };
+        let route_query = query.valhalla_route_query(
+            app_state,
+            &[hub, query.to_place],
+            costing,
+            0,
+            distance_units,
+            None,
+        )?;
+        let valhalla_response = fetch_valhalla_route(app_state, &route_query).await?;
+        let connection_response =
+            PlanResponseOk::from_valhalla(connection_mode, valhalla_response, None)?;
+        Ok(PlanResponseOk::stitch(otp_response, connection_response))
+    };
+    Some(result.await)
 }
 
-async fn otp_plan(
-    query: &web::Query<PlanQuery>,
-    req: HttpRequest,
-    app_state: &web::Data<AppState>,
-    primary_mode: &TravelMode,
-) -> Result<PlanResponseOk, PlanResponseErr> {
-    let Some(mut router_url) = app_state
-        .otp_cluster()
-        .find_router_url(query.from_place, query.to_place)
-    else {
-        Err(
-            Error::user("Transit directions not available for this area.")
-                .error_type(ErrorType::NoCoverageForArea),
-        )?
+/// Plan the trip with a single OTP router. An OTP error is returned as part of the response.
+async fn otp_plan_with_router(
+    query: &PlanQuery,
+    app_state: &AppState,
+    plan_endpoint: &OtpPlanEndpoint,
+    requested_time: Option<RequestedTime>,
+) -> Result<otp_api::PlanResponse, PlanResponseErr> {
+    if query.via.is_empty() {
+        let plan_request =
+            query.otp_plan_request(query.from_place, query.to_place, requested_time)?;
+        return fetch_otp_plan(plan_endpoint, &plan_request, app_state).await;
+    }
+
+    // OTP doesn't support intermediate stops, so we plan each segment of the trip separately
+    // and stitch the results together, departing from each stop as soon as we arrive.
+    let mut stitched_response: Option<otp_api::PlanResponse> = None;
+    for segment in query.waypoints().windows(2) {
+        let segment_requested_time = match &stitched_response {
+            None => requested_time,
+            Some(stitched_response) => Some(otp_local_departure_after(
+                &stitched_response.plan.itineraries[0],
+            )),
+        };
+        let plan_request =
+            query.otp_plan_request(segment[0], segment[1], segment_requested_time)?;
+
+        let mut segment_response = fetch_otp_plan(plan_endpoint, &plan_request, app_state).await?;
+        if segment_response.error.is_some() {
+            // No trip through every stop
+            return Ok(segment_response);
+        }
+        let itineraries = std::mem::take(&mut segment_response.plan.itineraries);
+        let Some(itinerary) = itineraries.into_iter().min_by_key(|i| i.end_time) else {
+            return Err(Error::server("OTP returned no itineraries for trip segment").into());
+        };
+
+        match &mut stitched_response {
+            None => {
+                segment_response.plan.itineraries.push(itinerary);
+                stitched_response = Some(segment_response);
+            }
+            Some(stitched_response) => {
+                stitched_response.plan.itineraries[0].append(itinerary);
+            }
+        }
+    }
+
+    Ok(stitched_response.expect("at least one trip segment"))
+}
+
+pub(super) async fn fetch_otp_plan(
+    plan_endpoint: &OtpPlanEndpoint,
+    plan_request: &otp_api::PlanRequest,
+    app_state: &AppState,
+) -> Result<otp_api::PlanResponse, PlanResponseErr> {
+    match plan_endpoint.api {
+        OtpApi::Rest => {
+            fetch_otp_rest_plan(plan_endpoint.url.clone(), plan_request, app_state).await
+        }
+        OtpApi::GraphQl => {
+            Ok(fetch_otp_graphql_plan(&plan_endpoint.url, plan_request, app_state).await?)
+        }
+    }
+}
+
+async fn fetch_otp_graphql_plan(
+    router_url: &url::Url,
+    plan_request: &otp_api::PlanRequest,
+    app_state: &AppState,
+) -> crate::Result<otp_api::PlanResponse> {
+    let time_zone = match plan_request.date {
+        Some(_) => Some(otp_time_zone(router_url, app_state).await?),
+        None => None,
     };
 
-    // if we end up building this manually rather than passing it through, we'll need to be sure
-    // to handle the bike+bus case
-    router_url.set_query(Some(req.query_string()));
-    log::debug!("found matching router. Forwarding request to: {router_url}",);
+    let request = otp_graphql::plan_connection_request(plan_request, time_zone)?;
+    log::debug!("found matching router. Sending GraphQL request to: {router_url}");
+    let graphql_response: otp_graphql::GraphQlResponse = app_state
+        .http_client()
+        .post(router_url.clone())
+        .timeout(app_state.upstream_config().otp_timeout)
+        .json(&request)
+        .send()
+        .await
+        .inspect_err(|e| log::error!("error while fetching from otp service: {e}"))?
+        .error_for_status()?
+        .json()
+        .await
+        .inspect_err(|e| log::error!("error while parsing otp response: {e}"))?;
+    graphql_response.into_plan_response()
+}
 
-    let otp_response: reqwest::Response = reqwest::get(router_url).await.map_err(|e| {
-        log::error!("error while fetching from otp service: {e}");
-        PlanResponseErr::from(Error::server(e))
-    })?;
+/// OTP's GraphQL API needs absolute times, so to plan for a local time we need the router's
+/// time zone, which we look up from its agencies, falling back to the configured one.
+async fn otp_time_zone(router_url: &url::Url, app_state: &AppState) -> crate::Result<Tz> {
+    if let Some(time_zone) = app_state.otp_time_zone(router_url) {
+        return Ok(time_zone);
+    }
+    match fetch_otp_agency_time_zone(router_url, app_state).await {
+        Ok(time_zone) => {
+            app_state.set_otp_time_zone(router_url.clone(), time_zone);
+            Ok(time_zone)
+        }
+        Err(e) => match app_state.upstream_config().otp_time_zone {
+            Some(time_zone) => {
+                log::warn!(
+                    "using configured time zone {time_zone} for OTP router {router_url}: {e}"
+                );
+                Ok(time_zone)
+            }
+            None => Err(e),
+        },
+    }
+}
+
+async fn fetch_otp_agency_time_zone(
+    router_url: &url::Url,
+    app_state: &AppState,
+) -> crate::Result<Tz> {
+    let response: otp_graphql::AgencyTimeZonesResponse = app_state
+        .http_client()
+        .post(router_url.clone())
+        .timeout(app_state.upstream_config().otp_timeout)
+        .json(&otp_graphql::agency_time_zones_request())
+        .send()
+        .await
+        .inspect_err(|e| log::error!("error while fetching agencies from otp service: {e}"))?
+        .error_for_status()?
+        .json()
+        .await
+        .inspect_err(|e| log::error!("error while parsing otp agencies response: {e}"))?;
+    response.into_time_zone()
+}
+
+async fn fetch_otp_rest_plan(
+    mut router_url: url::Url,
+    plan_request: &otp_api::PlanRequest,
+    app_state: &AppState,
+) -> Result<otp_api::PlanResponse, PlanResponseErr> {
+    let query_string = serde_urlencoded::to_string(plan_request).map_err(Error::server)?;
+    router_url.set_query(Some(&query_string));
+    log::debug!("found matching router. Sending request to: {router_url}");
+
+    let otp_response: reqwest::Response = app_state
+        .http_client()
+        .get(router_url)
+        .timeout(app_state.upstream_config().otp_timeout)
+        .send()
+        .await
+        .map_err(|e| {
+            log::error!("error while fetching from otp service: {e}");
+            PlanResponseErr::from(Error::server(e))
+        })?;
     if !otp_response.status().is_success() {
         log::warn!(
             "upstream HTTP Error from otp service: {}",
@@ -796,17 +1691,35 @@ async fn otp_plan(
     );
     response.content_type("application/json");
 
-    let otp_plan_response: otp_api::PlanResponse = otp_response.json().await.map_err(|e| {
+    otp_response.json().await.map_err(|e| {
         log::error!("error while parsing otp response: {e}");
         PlanResponseErr::from(Error::server(e))
-    })?;
-    PlanResponseOk::from_otp(
-        *primary_mode,
-        otp_plan_response,
-        query
-            .preferred_distance_units
-            .unwrap_or(DistanceUnit::Kilometers),
-    )
+    })
+}
+
+/// OTP expects points formatted as `lat,lon`
+pub(super) fn format_lat_lon(point: Point) -> String {
+    format!("{},{}", point.y(), point.x())
+}
+
+/// The first whole minute, in the router's local time, after `itinerary` arrives.
+fn otp_local_departure_after(itinerary: &otp_api::Itinerary) -> RequestedTime {
+    let time_zone_offset = itinerary
+        .legs
+        .last()
+        .and_then(|leg| leg.agency_time_zone_offset)
+        .unwrap_or_else(|| {
+            log::warn!("OTP itinerary is missing agencyTimeZoneOffset, assuming UTC");
+            0
+        });
+    const MILLIS_PER_MINUTE: i64 = 60 * 1000;
+    let local_millis = itinerary.end_time as i64 + time_zone_offset;
+    let local_millis =
+        (local_millis + MILLIS_PER_MINUTE - 1) / MILLIS_PER_MINUTE * MILLIS_PER_MINUTE;
+    let local_time = chrono::DateTime::from_timestamp_millis(local_millis)
+        .expect("timestamp in range")
+        .naive_utc();
+    RequestedTime::DepartAt(local_time)
 }
 
 #[cfg(test)]
@@ -828,7 +1741,8 @@ mod tests {
 
         let valhalla_response_result = valhalla_api::ValhallaRouteResponseResult::Ok(valhalla);
         let plan_response =
-            PlanResponseOk::from_valhalla(TravelMode::Walk, valhalla_response_result).unwrap();
+            PlanResponseOk::from_valhalla(TravelMode::Walk, valhalla_response_result, None)
+                .unwrap();
         assert_eq!(plan_response.plan.itineraries.len(), 3);
 
         // itineraries
@@ -877,8 +1791,13 @@ mod tests {
             File::open("tests/fixtures/requests/opentripplanner_transit_plan.json").unwrap();
         let otp: otp_api::PlanResponse =
             serde_json::from_reader(BufReader::new(stubbed_response)).unwrap();
-        let plan_response =
-            PlanResponseOk::from_otp(TravelMode::Transit, otp, DistanceUnit::Miles).unwrap();
+        let plan_response = PlanResponseOk::from_otp(
+            TravelMode::Transit,
+            otp,
+            DistanceUnit::Miles,
+            Language::English,
+        )
+        .unwrap();
 
         let itineraries = plan_response.plan.itineraries;
         assert_eq!(itineraries.len(), 6);
@@ -928,14 +1847,273 @@ mod tests {
         assert!(transit_leg.route_color.is_none());
     }
 
+    #[test]
+    fn parse_from_otp_transit_with_bicycle() {
+        let stubbed_response =
+            File::open("tests/fixtures/requests/opentripplanner_transit_with_bicycle_plan.json")
+                .unwrap();
+        let otp: otp_api::PlanResponse =
+            serde_json::from_reader(BufReader::new(stubbed_response)).unwrap();
+        let plan_response = PlanResponseOk::from_otp(
+            TravelMode::Transit,
+            otp,
+            DistanceUnit::Miles,
+            Language::English,
+        )
+        .unwrap();
+
+        let itinerary = plan_response
+            .plan
+            .itineraries
+            .iter()
+            .find(|itinerary| itinerary.legs.len() == 4)
+            .expect("itinerary with transit legs");
+        assert_eq!(itinerary.mode, TravelMode::Transit);
+        let leg_modes: Vec<_> = itinerary.legs.iter().map(|leg| leg.mode).collect();
+        assert_eq!(
+            leg_modes,
+            vec![
+                TravelMode::Bicycle,
+                TravelMode::Transit,
+                TravelMode::Transit,
+                TravelMode::Bicycle
+            ]
+        );
+        assert!(matches!(itinerary.legs[0].mode_leg, ModeLeg::NonTransit(_)));
+        assert!(matches!(itinerary.legs[1].mode_leg, ModeLeg::Transit(_)));
+    }
+
+    #[test]
+    fn localized_otp_instructions() {
+        let stubbed_response =
+            File::open("tests/fixtures/requests/opentripplanner_walk_plan.json").unwrap();
+        let otp: otp_api::PlanResponse =
+            serde_json::from_reader(BufReader::new(stubbed_response)).unwrap();
+        let response = PlanResponseOk::from_otp(
+            TravelMode::Walk,
+            otp,
+            DistanceUnit::Kilometers,
+            Language::German,
+        )
+        .unwrap();
+
+        let ModeLeg::NonTransit(leg) = &response.plan.itineraries[0].legs[0].mode_leg else {
+            panic!("expected a walking leg");
+        };
+        let maneuvers = &leg.maneuvers;
+        assert_eq!(
+            maneuvers[0].instruction.as_deref(),
+            Some("Gehen Sie Richtung Süden auf East Marginal Way South.")
+        );
+        assert_eq!(
+            maneuvers[2].instruction.as_deref(),
+            Some("Biegen Sie rechts in East Marginal Way South ab.")
+        );
+        assert_eq!(
+            maneuvers[2].verbal_post_transition_instruction.as_deref(),
+            Some("Weiter für 1,2 Kilometer.")
+        );
+        assert_eq!(
+            maneuvers.last().unwrap().instruction.as_deref(),
+            Some("Sie haben Ihr Ziel erreicht.")
+        );
+    }
+
+    #[test]
+    fn contextual_otp_instructions() {
+        let steps: Vec<otp_api::Step> = serde_json::from_value(json!([
+            { "relativeDirection": "DEPART", "absoluteDirection": "NORTH", "streetName": "path", "bogusName": true },
+            { "relativeDirection": "RIGHT", "streetName": "path", "bogusName": true },
+            { "relativeDirection": "LEFT", "streetName": "Main Street" },
+            { "relativeDirection": "SLIGHTLY_RIGHT", "streetName": "Main Street", "stayOn": true },
+            { "relativeDirection": "CONTINUE", "streetName": "Main Street", "stayOn": true },
+            { "relativeDirection": "CIRCLE_COUNTERCLOCKWISE", "streetName": "Oak Street", "exit": "3" },
+            { "relativeDirection": "LEFT", "streetName": "Pioneer Square", "area": true },
+            { "relativeDirection": "RIGHT", "streetName": "open area", "area": true, "bogusName": true },
+        ])
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|step| {
+            let mut step = step.clone();
+            let step_fields = step.as_object_mut().unwrap();
+            step_fields.insert("distance".to_string(), json!(10.0));
+            step_fields.insert("lon".to_string(), json!(-122.3));
+            step_fields.insert("lat".to_string(), json!(47.6));
+            step
+        })
+        .collect())
+        .unwrap();
+
+        let instructions: Vec<_> = steps
+            .iter()
+            .enumerate()
+            .map(|(idx, step)| {
+                maneuver_instruction(
+                    otp_api::TransitMode::Walk,
+                    step,
+                    &steps[..idx],
+                    Language::English,
+                )
+                .unwrap()
+            })
+            .collect();
+        assert_eq!(
+            instructions,
+            vec![
+                "Walk north on path.",
+                // Generated names are only mentioned once
+                "Turn right.",
+                "Turn left onto Main Street.",
+                "Bear right to stay on Main Street.",
+                "Continue on Main Street.",
+                "Take exit 3 at the roundabout.",
+                "Cross Pioneer Square.",
+                "Cross the open area.",
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_otp_and_valhalla() {
+        let stubbed_response =
+            File::open("tests/fixtures/requests/opentripplanner_walk_plan.json").unwrap();
+        let otp: otp_api::PlanResponse =
+            serde_json::from_reader(BufReader::new(stubbed_response)).unwrap();
+        let otp_response = PlanResponseOk::from_otp(
+            TravelMode::Walk,
+            otp,
+            DistanceUnit::Kilometers,
+            Language::English,
+        )
+        .unwrap();
+
+        let stubbed_response =
+            File::open("tests/fixtures/requests/valhalla_pedestrian_route.json").unwrap();
+        let valhalla: valhalla_api::RouteResponse =
+            serde_json::from_reader(BufReader::new(stubbed_response)).unwrap();
+        let valhalla_response = PlanResponseOk::from_valhalla(
+            TravelMode::Walk,
+            valhalla_api::ValhallaRouteResponseResult::Ok(valhalla),
+            None,
+        )
+        .unwrap();
+        assert_eq!(otp_response.plan.itineraries.len(), 1);
+        assert_eq!(valhalla_response.plan.itineraries.len(), 3);
+
+        // An itinerary is always similar to itself
+        let otp_itinerary = &otp_response.plan.itineraries[0];
+        assert!(otp_itinerary.is_similar_to(otp_itinerary));
+
+        let merged = PlanResponseOk::merge(otp_response.clone(), valhalla_response.clone(), 10);
+        let durations: Vec<_> = merged
+            .plan
+            .itineraries
+            .iter()
+            .map(|itinerary| itinerary.duration)
+            .collect();
+        let mut sorted_durations = durations.clone();
+        sorted_durations.sort_by(f64::total_cmp);
+        assert_eq!(durations, sorted_durations);
+        assert!(merged
+            .plan
+            .itineraries
+            .iter()
+            .any(|itinerary| itinerary.source == ItinerarySource::Otp));
+        assert!(merged
+            .plan
+            .itineraries
+            .iter()
+            .any(|itinerary| itinerary.source == ItinerarySource::Valhalla));
+
+        // Duplicates are dropped
+        let merged_with_self =
+            PlanResponseOk::merge(valhalla_response.clone(), valhalla_response, 10);
+        assert_eq!(merged_with_self.plan.itineraries.len(), 3);
+
+        let limited = PlanResponseOk::merge(otp_response, merged.clone(), 2);
+        assert_eq!(limited.plan.itineraries.len(), 2);
+    }
+
+    #[test]
+    fn stitch_otp_and_valhalla() {
+        let stubbed_response =
+            File::open("tests/fixtures/requests/opentripplanner_transit_plan.json").unwrap();
+        let otp: otp_api::PlanResponse =
+            serde_json::from_reader(BufReader::new(stubbed_response)).unwrap();
+        let otp_response = PlanResponseOk::from_otp(
+            TravelMode::Transit,
+            otp,
+            DistanceUnit::Kilometers,
+            Language::English,
+        )
+        .unwrap();
+
+        let stubbed_response =
+            File::open("tests/fixtures/requests/valhalla_pedestrian_route.json").unwrap();
+        let valhalla: valhalla_api::RouteResponse =
+            serde_json::from_reader(BufReader::new(stubbed_response)).unwrap();
+        let connection_response = PlanResponseOk::from_valhalla(
+            TravelMode::Walk,
+            valhalla_api::ValhallaRouteResponseResult::Ok(valhalla),
+            None,
+        )
+        .unwrap();
+
+        let stitched = PlanResponseOk::stitch(otp_response.clone(), connection_response.clone());
+        assert_eq!(
+            stitched.plan.itineraries.len(),
+            otp_response.plan.itineraries.len()
+        );
+
+        let connection = &connection_response.plan.itineraries[0];
+        for (stitched, transit) in stitched
+            .plan
+            .itineraries
+            .iter()
+            .zip(&otp_response.plan.itineraries)
+        {
+            assert_eq!(stitched.source, ItinerarySource::Stitched);
+            assert_eq!(stitched.mode, TravelMode::Transit);
+            assert_eq!(
+                stitched.legs.len(),
+                transit.legs.len() + connection.legs.len()
+            );
+            assert_eq!(stitched.start_time, transit.start_time);
+
+            // The connection departs as soon as transit arrives
+            let first_connection_leg = &stitched.legs[transit.legs.len()];
+            assert_eq!(first_connection_leg.mode, TravelMode::Walk);
+            assert_eq!(first_connection_leg.start_time, transit.end_time);
+            assert_eq!(
+                stitched.end_time,
+                transit.end_time
+                    + connection
+                        .end_time
+                        .duration_since(connection.start_time)
+                        .unwrap()
+            );
+            assert_relative_eq!(
+                stitched.distance_meters(),
+                transit.distance_meters() + connection.distance_meters(),
+                epsilon = 1e-6
+            );
+        }
+    }
+
     #[test]
     fn serialize_response_from_otp() {
         let stubbed_response =
             File::open("tests/fixtures/requests/opentripplanner_transit_plan.json").unwrap();
         let otp: otp_api::PlanResponse =
             serde_json::from_reader(BufReader::new(stubbed_response)).unwrap();
-        let plan_response =
-            PlanResponseOk::from_otp(TravelMode::Transit, otp, DistanceUnit::Miles).unwrap();
+        let plan_response = PlanResponseOk::from_otp(
+            TravelMode::Transit,
+            otp,
+            DistanceUnit::Miles,
+            Language::English,
+        )
+        .unwrap();
         let response = serde_json::to_string(&plan_response).unwrap();
         let parsed_response: serde_json::Value = serde_json::from_str(&response).unwrap();
         let response_object = parsed_response.as_object().expect("expected Object");
@@ -1033,7 +2211,8 @@ mod tests {
 
         let valhalla_response_result = valhalla_api::ValhallaRouteResponseResult::Ok(valhalla);
         let plan_response =
-            PlanResponseOk::from_valhalla(TravelMode::Walk, valhalla_response_result).unwrap();
+            PlanResponseOk::from_valhalla(TravelMode::Walk, valhalla_response_result, None)
+                .unwrap();
 
         let response = serde_json::to_string(&plan_response).unwrap();
         let parsed_response: serde_json::Value = serde_json::from_str(&response).unwrap();
@@ -1162,6 +2341,544 @@ mod tests {
         assert_eq!(plan_error.error.error_code, 2154);
     }
 
+    #[test]
+    fn parse_query_with_via() {
+        let query = Query::<PlanQuery>::from_query(
+            "fromPlace=47.575837,-122.339414&toPlace=47.651048,-122.347234&via=47.6,-122.34|47.62,-122.35&numItineraries=2&mode=WALK",
+        )
+        .unwrap();
+        assert_eq!(
+            query.waypoints(),
+            vec![
+                geo::point!(x: -122.339414, y: 47.575837),
+                geo::point!(x: -122.34, y: 47.6),
+                geo::point!(x: -122.35, y: 47.62),
+                geo::point!(x: -122.347234, y: 47.651048),
+            ]
+        );
+
+        let query = Query::<PlanQuery>::from_query(
+            "fromPlace=47.575837,-122.339414&toPlace=47.651048,-122.347234&numItineraries=2&mode=WALK",
+        )
+        .unwrap();
+        assert_eq!(query.waypoints().len(), 2);
+
+        let bad_via = Query::<PlanQuery>::from_query(
+            "fromPlace=47.575837,-122.339414&toPlace=47.651048,-122.347234&via=47.6|47.62,-122.35&numItineraries=2&mode=WALK",
+        );
+        assert!(bad_via.is_err());
+    }
+
+    #[test]
+    fn parse_requested_time() {
+        let base = "fromPlace=47.575837,-122.339414&toPlace=47.651048,-122.347234&numItineraries=2&mode=CAR";
+        let parse = |params: &str| {
+            Query::<PlanQuery>::from_query(&format!("{base}{params}"))
+                .unwrap()
+                .requested_time()
+        };
+        let expected_local_time = chrono::NaiveDate::from_ymd_opt(2024, 5, 17)
+            .unwrap()
+            .and_hms_opt(8, 30, 0)
+            .unwrap();
+
+        assert_eq!(parse("").unwrap(), None);
+        assert_eq!(
+            parse("&date=2024-05-17&time=08:30").unwrap(),
+            Some(RequestedTime::DepartAt(expected_local_time))
+        );
+        assert_eq!(
+            parse("&date=05-17-2024&time=8:30am&arriveBy=true").unwrap(),
+            Some(RequestedTime::ArriveBy(expected_local_time))
+        );
+        assert!(parse("&time=08:30").is_err());
+        assert!(parse("&date=2024-05-17").is_err());
+        assert!(parse("&date=tomorrow&time=08:30").is_err());
+        assert!(parse("&date=2024-05-17&time=25:00").is_err());
+    }
+
+    #[test]
+    fn valhalla_times_from_requested_time() {
+        let stubbed_response =
+            File::open("tests/fixtures/requests/valhalla_pedestrian_route.json").unwrap();
+        let mut valhalla: valhalla_api::RouteResponse =
+            serde_json::from_reader(BufReader::new(stubbed_response)).unwrap();
+        for location in &mut valhalla.trip.locations {
+            location.time_zone_offset = Some("-07:00".to_string());
+        }
+        let local_time = chrono::NaiveDate::from_ymd_opt(2024, 5, 17)
+            .unwrap()
+            .and_hms_opt(8, 0, 0)
+            .unwrap();
+        // 2024-05-17T08:00-07:00
+        let requested_instant = system_time_from_millis(1715958000000);
+
+        let depart_at = Itinerary::from_valhalla(
+            &valhalla.trip,
+            TravelMode::Walk,
+            Some(RequestedTime::DepartAt(local_time)),
+        );
+        assert_eq!(depart_at.start_time, requested_instant);
+        assert_eq!(depart_at.legs[0].start_time, requested_instant);
+        assert_eq!(
+            depart_at.end_time,
+            requested_instant + Duration::from_millis(6488443)
+        );
+
+        let arrive_by = Itinerary::from_valhalla(
+            &valhalla.trip,
+            TravelMode::Walk,
+            Some(RequestedTime::ArriveBy(local_time)),
+        );
+        assert_eq!(arrive_by.end_time, requested_instant);
+        assert_eq!(arrive_by.legs.last().unwrap().end_time, requested_instant);
+        assert_eq!(
+            arrive_by.start_time,
+            requested_instant - Duration::from_millis(6488443)
+        );
+    }
+
+    #[test]
+    fn stitch_otp_itineraries() {
+        let stubbed_response =
+            File::open("tests/fixtures/requests/opentripplanner_walk_plan.json").unwrap();
+        let otp: otp_api::PlanResponse =
+            serde_json::from_reader(BufReader::new(stubbed_response)).unwrap();
+        let first_segment = otp.plan.itineraries[0].clone();
+
+        let mut second_segment = first_segment.clone();
+        let offset = 10 * 60 * 1000;
+        second_segment.start_time += offset;
+        second_segment.end_time += offset;
+        for leg in &mut second_segment.legs {
+            leg.start_time += offset;
+            leg.end_time += offset;
+        }
+
+        let mut stitched = first_segment.clone();
+        stitched.append(second_segment.clone());
+        assert_eq!(stitched.legs.len(), 2);
+        assert_eq!(stitched.start_time, first_segment.start_time);
+        assert_eq!(stitched.end_time, second_segment.end_time);
+        assert_eq!(
+            stitched.duration,
+            (second_segment.end_time - first_segment.start_time) / 1000
+        );
+
+        let itinerary = Itinerary::from_otp(
+            &stitched,
+            TravelMode::Walk,
+            DistanceUnit::Meters,
+            Language::English,
+        )
+        .expect("valid itinerary");
+        assert_eq!(itinerary.legs.len(), 2);
+    }
+
+    #[test]
+    fn otp_departure_after_arrival() {
+        let stubbed_response =
+            File::open("tests/fixtures/requests/opentripplanner_transit_plan.json").unwrap();
+        let otp: otp_api::PlanResponse =
+            serde_json::from_reader(BufReader::new(stubbed_response)).unwrap();
+        let itinerary = &otp.plan.itineraries[0];
+        let departure = otp_local_departure_after(itinerary);
+
+        let arrival = chrono::DateTime::from_timestamp_millis(itinerary.end_time as i64).unwrap();
+        // Seattle, during daylight savings time
+        let local_arrival = arrival.naive_utc() - chrono::Duration::hours(7);
+        let RequestedTime::DepartAt(local_departure) = departure else {
+            panic!("expected a departure time, got: {departure:?}");
+        };
+        assert!(local_departure >= local_arrival);
+        assert!(local_departure - local_arrival < chrono::Duration::minutes(1));
+        assert_eq!(local_departure.and_utc().timestamp() % 60, 0);
+    }
+
+    #[test]
+    fn otp_plan_request_from_query() {
+        let base = "fromPlace=47.575837,-122.339414&toPlace=47.651048,-122.347234&numItineraries=3&mode=TRANSIT,BICYCLE";
+        let parse = |params: &str| {
+            let query = Query::<PlanQuery>::from_query(&format!("{base}{params}")).unwrap();
+            let requested_time = query.requested_time().unwrap();
+            query.otp_plan_request(query.from_place, query.to_place, requested_time)
+        };
+
+        let request =
+            parse("&date=2024-05-17&time=8:30am&arriveBy=true&wheelchair=true&walkSpeed=1.2")
+                .unwrap();
+        assert_eq!(request.from_place, "47.575837,-122.339414");
+        assert_eq!(request.to_place, "47.651048,-122.347234");
+        assert_eq!(
+            request.mode,
+            vec![otp_api::RequestMode::Transit, otp_api::RequestMode::Bicycle]
+        );
+        assert_eq!(request.date.as_deref(), Some("2024-05-17"));
+        assert_eq!(request.time.as_deref(), Some("08:30"));
+        assert!(request.arrive_by);
+        assert!(request.wheelchair);
+        assert_eq!(request.walk_speed, Some(1.2));
+        assert_eq!(request.max_walk_distance, None);
+
+        // unrecognized parameters are not forwarded
+        let request = parse("&optimize=FLAT").unwrap();
+        let query_string = serde_urlencoded::to_string(&request).unwrap();
+        assert!(!query_string.contains("optimize"));
+
+        let park_and_ride = Query::<PlanQuery>::from_query(
+            "fromPlace=47.575837,-122.339414&toPlace=47.651048,-122.347234&numItineraries=3&mode=CAR_PARK,TRANSIT",
+        )
+        .unwrap()
+        .otp_plan_request(Point::new(0.0, 0.0), Point::new(1.0, 1.0), None)
+        .unwrap();
+        assert_eq!(
+            park_and_ride.mode,
+            vec![otp_api::RequestMode::Transit, otp_api::RequestMode::CarPark]
+        );
+
+        let err = parse("&walkSpeed=-1").unwrap_err();
+        assert_eq!(err.error_type, ErrorType::User);
+        let err = parse("&maxWalkDistance=NaN").unwrap_err();
+        assert_eq!(err.error_type, ErrorType::User);
+    }
+
+    #[test]
+    fn valhalla_route_query_from_query() {
+        let app_state = AppState::new(
+            url::Url::parse("http://127.0.0.1:1").unwrap(),
+            std::path::PathBuf::from("tests/fixtures/low_res_elevation_tifs"),
+        );
+        let base = "fromPlace=47.575837,-122.339414&toPlace=47.651048,-122.347234&numItineraries=2";
+        let route_query = |params: &str, costing: valhalla_api::ModeCosting| {
+            let query = Query::<PlanQuery>::from_query(&format!("{base}{params}")).unwrap();
+            query.valhalla_route_query(
+                &app_state,
+                &query.waypoints(),
+                costing,
+                query.num_itineraries,
+                DistanceUnit::Kilometers,
+                None,
+            )
+        };
+
+        let plain = route_query("&mode=BICYCLE", valhalla_api::ModeCosting::Bicycle).unwrap();
+        assert!(serde_json::to_value(&plain)
+            .unwrap()
+            .get("costing_options")
+            .is_none());
+
+        let params = "&mode=BICYCLE&useHills=0.1&bicycleType=ROAD&topSpeed=25&avoidHighways=true";
+        let bicycle = route_query(params, valhalla_api::ModeCosting::Bicycle).unwrap();
+        assert_eq!(
+            serde_json::to_value(&bicycle).unwrap()["costing_options"],
+            json!({
+                "bicycle": { "use_hills": 0.1, "bicycle_type": "Road", "cycling_speed": 25.0 }
+            })
+        );
+
+        let params = "&mode=CAR&useHills=0.1&useFerry=0&topSpeed=90&avoidHighways=true";
+        let auto = route_query(params, valhalla_api::ModeCosting::Auto).unwrap();
+        assert_eq!(
+            serde_json::to_value(&auto).unwrap()["costing_options"],
+            json!({ "auto": { "use_ferry": 0.0, "use_highways": 0.0, "top_speed": 90.0 } })
+        );
+
+        let wheelchair = route_query(
+            "&mode=WALK&wheelchair=true",
+            valhalla_api::ModeCosting::Pedestrian,
+        )
+        .unwrap();
+        assert_eq!(
+            serde_json::to_value(&wheelchair).unwrap()["costing_options"],
+            json!({ "pedestrian": { "type": "wheelchair" } })
+        );
+
+        let plain_json = serde_json::to_value(&plain).unwrap();
+        assert!(plain_json.get("language").is_none());
+        let german = route_query(
+            "&mode=WALK&lang=de-DE",
+            valhalla_api::ModeCosting::Pedestrian,
+        )
+        .unwrap();
+        assert_eq!(serde_json::to_value(&german).unwrap()["language"], "de-DE");
+
+        let err = route_query(
+            "&mode=WALK&useHills=2",
+            valhalla_api::ModeCosting::Pedestrian,
+        )
+        .unwrap_err();
+        assert_eq!(err.error_type, ErrorType::User);
+        let err =
+            route_query("&mode=CAR&topSpeed=-5", valhalla_api::ModeCosting::Auto).unwrap_err();
+        assert_eq!(err.error_type, ErrorType::User);
+    }
+
+    #[test]
+    fn flag_inaccessible_maneuvers() {
+        let stubbed_response =
+            File::open("tests/fixtures/requests/valhalla_pedestrian_route.json").unwrap();
+        let mut valhalla: valhalla_api::RouteResponse =
+            serde_json::from_reader(BufReader::new(stubbed_response)).unwrap();
+        valhalla.trip.legs[0].maneuvers[1].r#type = ManeuverType::StepsEnter;
+        valhalla.trip.legs[0].maneuvers[2].r#type = ManeuverType::ElevatorEnter;
+
+        let itinerary = Itinerary::from_valhalla(&valhalla.trip, TravelMode::Walk, None);
+        let json = serde_json::to_value(&itinerary).unwrap();
+        let maneuvers = json["legs"][0]["nonTransitLeg"]["maneuvers"]
+            .as_array()
+            .unwrap();
+        assert!(maneuvers[0].get("accessibilityFlag").is_none());
+        assert_eq!(maneuvers[1]["accessibilityFlag"], "STEPS");
+        assert_eq!(maneuvers[2]["accessibilityFlag"], "ELEVATOR");
+    }
+
+    #[actix_web::test]
+    async fn no_wheelchair_accessible_route() {
+        // Responds to every request like Valhalla does when there's no route
+        let valhalla_endpoint = crate::test_util::stub_upstream(|_request_line| {
+            let body = r#"{"error_code":442,"error":"No path could be found for input","status_code":400,"status":"Bad Request"}"#;
+            (400, body.to_string())
+        });
+        let app_state = Data::new(AppState::new(
+            valhalla_endpoint,
+            std::path::PathBuf::from("tests/fixtures/low_res_elevation_tifs"),
+        ));
+
+        let base = "fromPlace=47.575837,-122.339414&toPlace=47.651048,-122.347234&numItineraries=2&mode=WALK";
+        let query = Query::<PlanQuery>::from_query(&format!("{base}&wheelchair=true")).unwrap();
+        let err = _get_plan(query, app_state.clone()).await.unwrap_err();
+        assert!(err.is_no_path());
+        assert_eq!(err.error.error_code, 2442);
+        assert_eq!(
+            err.error.message,
+            "No wheelchair accessible route was found."
+        );
+
+        let query = Query::<PlanQuery>::from_query(base).unwrap();
+        let err = _get_plan(query, app_state).await.unwrap_err();
+        assert_eq!(err.error.message, "No path could be found for input");
+    }
+
+    #[actix_web::test]
+    async fn avoid_areas_and_locations() {
+        let app_state = AppState::new(
+            url::Url::parse("http://127.0.0.1:1").unwrap(),
+            std::path::PathBuf::from("tests/fixtures/low_res_elevation_tifs"),
+        );
+        let base = "fromPlace=47.575837,-122.339414&toPlace=47.651048,-122.347234&numItineraries=2";
+        let parse = |params: &str| Query::<PlanQuery>::from_query(&format!("{base}{params}"));
+        let encode =
+            |s: &str| url::form_urlencoded::byte_serialize(s.as_bytes()).collect::<String>();
+
+        let geojson = r#"{"type":"Polygon","coordinates":[[[-122.35,47.6],[-122.34,47.6],[-122.34,47.61],[-122.35,47.6]]]}"#;
+        let from_geojson = parse(&format!(
+            "&mode=CAR&avoidAreas={}&avoidLocations=47.62,-122.34|47.63,-122.35",
+            encode(geojson)
+        ))
+        .unwrap();
+        let route_query = from_geojson
+            .valhalla_route_query(
+                &app_state,
+                &from_geojson.waypoints(),
+                valhalla_api::ModeCosting::Auto,
+                2,
+                DistanceUnit::Kilometers,
+                None,
+            )
+            .unwrap();
+        assert_eq!(
+            route_query.exclude_polygons,
+            vec![vec![
+                [-122.35, 47.6],
+                [-122.34, 47.6],
+                [-122.34, 47.61],
+                [-122.35, 47.6]
+            ]]
+        );
+        assert_eq!(
+            route_query.exclude_locations,
+            vec![
+                LonLat {
+                    lon: -122.34,
+                    lat: 47.62
+                },
+                LonLat {
+                    lon: -122.35,
+                    lat: 47.63
+                }
+            ]
+        );
+
+        let ring = wkt!(LINESTRING(-122.35 47.6,-122.34 47.6,-122.34 47.61,-122.35 47.6));
+        let encoded = polyline::encode_coordinates(ring.0.iter().copied(), 6).unwrap();
+        let from_polyline = parse(&format!(
+            "&mode=CAR&avoidAreas={}",
+            encode(&format!("{encoded},{encoded}"))
+        ))
+        .unwrap();
+        assert_eq!(from_polyline.avoid_areas.len(), 2);
+        assert_eq!(from_polyline.avoid_areas[0], from_geojson.avoid_areas[0]);
+
+        // Not a polygon
+        let point = r#"{"type":"Point","coordinates":[-122.35,47.6]}"#;
+        assert!(parse(&format!("&mode=CAR&avoidAreas={}", encode(point))).is_err());
+
+        let transit = parse("&mode=TRANSIT&avoidLocations=47.62,-122.34").unwrap();
+        let err = _get_plan(transit, Data::new(app_state)).await.unwrap_err();
+        assert_eq!(err.error.status_code, 400);
+        assert_eq!(err.error.error_code, ErrorType::User as u32);
+    }
+
+    #[actix_web::test]
+    async fn report_snapped_endpoints() {
+        let located = json!([
+            {
+                "input_lat": 47.575837,
+                "input_lon": -122.339414,
+                "edges": [{
+                    "correlated_lat": 47.5759,
+                    "correlated_lon": -122.339414,
+                    "edge_info": { "names": ["1st Avenue South"] }
+                }]
+            },
+            { "input_lat": 47.651048, "input_lon": -122.347234, "edges": null }
+        ])
+        .to_string();
+        let app_state = |route: (u16, String)| {
+            let located = located.clone();
+            // Responds like Valhalla to `/locate`, and with `route` to anything else
+            let valhalla_endpoint = crate::test_util::stub_upstream(move |request_line| {
+                if request_line.starts_with("POST /locate ") {
+                    (200, located.clone())
+                } else {
+                    route.clone()
+                }
+            });
+            Data::new(AppState::new(
+                valhalla_endpoint,
+                std::path::PathBuf::from("tests/fixtures/low_res_elevation_tifs"),
+            ))
+        };
+        let route =
+            std::fs::read_to_string("tests/fixtures/requests/valhalla_auto_route.json").unwrap();
+        let routing_app_state = app_state((200, route));
+
+        let base = "fromPlace=47.575837,-122.339414&toPlace=47.651048,-122.347234&numItineraries=1&mode=CAR";
+        let query = Query::<PlanQuery>::from_query(&format!("{base}&reportSnapping=true")).unwrap();
+        let response = _get_plan(query, routing_app_state.clone()).await.unwrap();
+        let json = serde_json::to_value(&response).unwrap();
+        let legs = json["plan"]["itineraries"][0]["legs"].as_array().unwrap();
+        let from_snap = &legs[0]["fromPlace"]["snap"];
+        assert_eq!(from_snap["streetName"], "1st Avenue South");
+        assert_relative_eq!(
+            from_snap["distanceMeters"].as_f64().unwrap(),
+            7.0,
+            epsilon = 0.1
+        );
+        // Nowhere to snap to
+        assert!(legs.last().unwrap()["toPlace"].get("snap").is_none());
+
+        let query = Query::<PlanQuery>::from_query(base).unwrap();
+        let response = _get_plan(query, routing_app_state).await.unwrap();
+        let json = serde_json::to_value(&response).unwrap();
+        assert!(json["plan"]["itineraries"][0]["legs"][0]["fromPlace"]
+            .get("snap")
+            .is_none());
+
+        // Explain why there's no path
+        let no_path = r#"{"error_code":171,"error":"No suitable edges near location","status_code":400,"status":"Bad Request"}"#;
+        let no_path_app_state = app_state((400, no_path.to_string()));
+        let query = Query::<PlanQuery>::from_query(&format!("{base}&reportSnapping=true")).unwrap();
+        let err = _get_plan(query, no_path_app_state.clone())
+            .await
+            .unwrap_err();
+        let json = serde_json::to_value(&err).unwrap();
+        assert_eq!(
+            json["error"]["snapping"]["fromPlace"]["streetName"],
+            "1st Avenue South"
+        );
+        assert!(json["error"]["snapping"]["toPlace"].is_null());
+
+        let query = Query::<PlanQuery>::from_query(base).unwrap();
+        let err = _get_plan(query, no_path_app_state).await.unwrap_err();
+        let json = serde_json::to_value(&err).unwrap();
+        assert!(json["error"].get("snapping").is_none());
+    }
+
+    #[actix_web::test]
+    async fn valhalla_timeout() {
+        // Accepts connections, but never responds
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let valhalla_endpoint =
+            url::Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+        let mut app_state = AppState::new(
+            valhalla_endpoint,
+            std::path::PathBuf::from("tests/fixtures/low_res_elevation_tifs"),
+        );
+        app_state.set_upstream_config(crate::api::UpstreamConfig {
+            valhalla_timeout: Duration::from_millis(100),
+            ..Default::default()
+        });
+
+        let query = Query::<PlanQuery>::from_query(
+            "fromPlace=47.575837,-122.339414&toPlace=47.651048,-122.347234&numItineraries=2&mode=CAR",
+        )
+        .unwrap();
+        let started_at = std::time::Instant::now();
+        let result = _get_plan(query, Data::new(app_state)).await;
+        let err = result.expect_err("request should time out");
+        assert_eq!(err.error.status_code, 500);
+        assert!(started_at.elapsed() < Duration::from_secs(5));
+    }
+
+    #[actix_web::test]
+    async fn race_gives_up_on_otp_shortly_after_valhalla() {
+        let body =
+            std::fs::read_to_string("tests/fixtures/requests/valhalla_pedestrian_route.json")
+                .unwrap();
+        let valhalla_endpoint =
+            crate::test_util::stub_upstream(move |_request_line| (200, body.clone()));
+        let mut app_state = AppState::new(
+            valhalla_endpoint,
+            std::path::PathBuf::from("tests/fixtures/low_res_elevation_tifs"),
+        );
+        app_state.set_upstream_config(crate::api::UpstreamConfig {
+            race_otp_and_valhalla: true,
+            otp_race_grace: Duration::from_millis(100),
+            ..Default::default()
+        });
+
+        // Accepts connections, but never responds
+        let otp_listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let otp_endpoint = url::Url::parse(&format!(
+            "http://{}/otp/routers",
+            otp_listener.local_addr().unwrap()
+        ))
+        .unwrap();
+        app_state.update_otp_cluster(|cluster| {
+            cluster.set_endpoint_routers(
+                otp_endpoint.clone(),
+                vec![crate::otp::OTPRouter::new(
+                    otp_endpoint.clone(),
+                    "default".to_string(),
+                    wkt!(POLYGON((-123. 47.,-122. 47.,-122. 48.,-123. 48.,-123. 47.))),
+                )],
+            )
+        });
+
+        let query = Query::<PlanQuery>::from_query(
+            "fromPlace=47.575837,-122.339414&toPlace=47.651048,-122.347234&numItineraries=2&mode=WALK",
+        )
+        .unwrap();
+        let started_at = std::time::Instant::now();
+        let response = _get_plan(query, Data::new(app_state))
+            .await
+            .expect("should fall back to Valhalla's route");
+        assert!(started_at.elapsed() < Duration::from_secs(5));
+        assert_eq!(response.plan.itineraries[0].legs[0].mode, TravelMode::Walk);
+        drop(otp_listener);
+    }
+
     #[test]
     fn maneuver_bearing() {
         let a = wkt!(LINESTRING(0. 0.,1. 0.,1. 1.));
@@ -1,20 +1,91 @@
-use crate::TravelMode;
+use crate::{Error, TravelMode};
 use serde::de::Visitor;
 use serde::{de, de::IntoDeserializer, Deserialize, Deserializer};
 use std::fmt;
 
-// Comma separated list of travel modes
+// Comma separated list of travel modes, in any order.
+//
+// In addition to the plain `TravelMode`s, `BICYCLE_PARK` and `CAR_PARK` can be combined with
+// `TRANSIT` to park a bike or car at a station before boarding.
 #[derive(Debug, PartialEq, Eq, Clone)]
-pub struct TravelModes(Vec<TravelMode>);
+pub struct TravelModes {
+    modes: Vec<TravelMode>,
+    /// The bike or car is parked before boarding transit, rather than brought along.
+    parks_vehicle: bool,
+}
+
+/// How a transit rider gets to and from their transit stops.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TransitAccess {
+    Walk,
+    /// Bring a bike aboard transit vehicles, riding it before and after.
+    BikeOnBoard,
+    /// Ride a bike to a station and park it there.
+    BikeAndRide,
+    /// Drive to a station and park there.
+    ParkAndRide,
+}
+
 impl TravelModes {
     pub fn len(&self) -> usize {
-        self.0.len()
+        self.modes.len()
     }
     pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.modes.is_empty()
     }
     pub fn first(&self) -> Option<&TravelMode> {
-        self.0.first()
+        self.modes.first()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &TravelMode> {
+        self.modes.iter()
+    }
+
+    fn contains(&self, mode: TravelMode) -> bool {
+        self.modes.contains(&mode)
+    }
+
+    /// The mode which determines how the trip is planned - transit, if present, otherwise the
+    /// only requested mode.
+    pub fn primary_mode(&self) -> Option<TravelMode> {
+        if self.contains(TravelMode::Transit) {
+            Some(TravelMode::Transit)
+        } else {
+            self.first().copied()
+        }
+    }
+
+    /// How the rider gets to and from transit, or `None` if the trip doesn't include transit.
+    pub fn transit_access(&self) -> crate::Result<Option<TransitAccess>> {
+        if !self.contains(TravelMode::Transit) {
+            if self.parks_vehicle {
+                return Err(Error::user(
+                    "BICYCLE_PARK and CAR_PARK can only be combined with TRANSIT",
+                ));
+            }
+            if self.len() > 1 {
+                return Err(Error::user(format!(
+                    "unsupported combination of modes: {:?}",
+                    self.modes
+                )));
+            }
+            return Ok(None);
+        }
+
+        let bicycle = self.contains(TravelMode::Bicycle);
+        let car = self.contains(TravelMode::Car);
+        let access = match (bicycle, car) {
+            (false, false) => TransitAccess::Walk,
+            (true, false) if self.parks_vehicle => TransitAccess::BikeAndRide,
+            (true, false) => TransitAccess::BikeOnBoard,
+            // Cars aren't allowed aboard, so any transit trip with a car is park and ride
+            (false, true) => TransitAccess::ParkAndRide,
+            (true, true) => {
+                return Err(Error::user(
+                    "BICYCLE and CAR cannot both be combined with TRANSIT",
+                ))
+            }
+        };
+        Ok(Some(access))
     }
 }
 
@@ -36,14 +107,72 @@ impl<'de> Deserialize<'de> for TravelModes {
             where
                 E: de::Error,
             {
+                let mut parks_vehicle = false;
                 let modes = value
                     .split(',')
-                    .map(|s| TravelMode::deserialize(s.into_deserializer()))
+                    .map(|s| match s {
+                        "BICYCLE_PARK" => {
+                            parks_vehicle = true;
+                            Ok(TravelMode::Bicycle)
+                        }
+                        "CAR_PARK" => {
+                            parks_vehicle = true;
+                            Ok(TravelMode::Car)
+                        }
+                        other => TravelMode::deserialize(other.into_deserializer()),
+                    })
                     .collect::<std::result::Result<_, _>>()?;
-                Ok(TravelModes(modes))
+                Ok(TravelModes {
+                    modes,
+                    parks_vehicle,
+                })
             }
         }
 
         deserializer.deserialize_str(CommaSeparatedVecVisitor)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(modes: &str) -> TravelModes {
+        TravelModes::deserialize(modes.into_deserializer())
+            .map_err(|e: de::value::Error| e)
+            .unwrap()
+    }
+
+    #[test]
+    fn primary_mode_is_order_independent() {
+        assert_eq!(
+            parse("TRANSIT,BICYCLE").primary_mode(),
+            Some(TravelMode::Transit)
+        );
+        assert_eq!(
+            parse("BICYCLE,TRANSIT").primary_mode(),
+            Some(TravelMode::Transit)
+        );
+        assert_eq!(parse("WALK").primary_mode(), Some(TravelMode::Walk));
+    }
+
+    #[test]
+    fn transit_access() {
+        let access = |modes: &str| parse(modes).transit_access().unwrap();
+        assert_eq!(access("CAR"), None);
+        assert_eq!(access("TRANSIT"), Some(TransitAccess::Walk));
+        assert_eq!(access("WALK,TRANSIT"), Some(TransitAccess::Walk));
+        assert_eq!(access("TRANSIT,BICYCLE"), Some(TransitAccess::BikeOnBoard));
+        assert_eq!(access("BICYCLE,TRANSIT"), Some(TransitAccess::BikeOnBoard));
+        assert_eq!(
+            access("BICYCLE_PARK,TRANSIT"),
+            Some(TransitAccess::BikeAndRide)
+        );
+        assert_eq!(access("TRANSIT,CAR_PARK"), Some(TransitAccess::ParkAndRide));
+        assert_eq!(access("CAR,TRANSIT"), Some(TransitAccess::ParkAndRide));
+
+        assert!(parse("BICYCLE_PARK").transit_access().is_err());
+        assert!(parse("WALK,CAR").transit_access().is_err());
+        assert!(parse("TRANSIT,BICYCLE,CAR").transit_access().is_err());
+    }
+}
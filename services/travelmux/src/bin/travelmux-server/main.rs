@@ -4,7 +4,10 @@ use url::Url;
 use std::env;
 use std::fs;
 use std::path::PathBuf;
-use travelmux::api::{self, AppState};
+use std::time::Duration;
+use travelmux::api::health::{DependencyKind, ReadinessConfig};
+use travelmux::api::{self, AppState, UpstreamConfig};
+use travelmux::otp::OtpApi;
 use travelmux::Result;
 
 #[actix_web::main]
@@ -19,7 +22,7 @@ async fn main() -> Result<()> {
         let bin_name = env::args()
             .next()
             .unwrap_or_else(|| "<bin name>".to_string());
-        panic!("No endpoints specified. Usage: {bin_name} https://valhalla.example.com https://endpoint1.example.com/otp/routers https://endpoint2.example.com/otp/routers")
+        panic!("No endpoints specified. Usage: {bin_name} https://valhalla.example.com https://endpoint1.example.com/otp/routers graphql+https://endpoint2.example.com/otp/routers")
     };
 
     let Ok(valhalla_endpoint) = Url::parse(&valhalla_endpoint) else {
@@ -46,15 +49,104 @@ async fn main() -> Result<()> {
     }
     let mut app_state = AppState::new(valhalla_endpoint, elevation_dir);
 
+    let default_upstream_config = UpstreamConfig::default();
+    app_state.set_upstream_config(UpstreamConfig {
+        otp_timeout: env_millis("OTP_TIMEOUT_MS").unwrap_or(default_upstream_config.otp_timeout),
+        valhalla_timeout: env_millis("VALHALLA_TIMEOUT_MS")
+            .unwrap_or(default_upstream_config.valhalla_timeout),
+        race_otp_and_valhalla: std::env::var("RACE_OTP_AND_VALHALLA")
+            .map(|s| {
+                s.parse()
+                    .unwrap_or_else(|_| panic!("malformed RACE_OTP_AND_VALHALLA specified: `{s}`"))
+            })
+            .unwrap_or(default_upstream_config.race_otp_and_valhalla),
+        otp_race_grace: env_millis("OTP_RACE_GRACE_MS")
+            .unwrap_or(default_upstream_config.otp_race_grace),
+        fall_back_to_next_otp_router: std::env::var("OTP_ROUTER_FALLBACK")
+            .map(|s| {
+                s.parse()
+                    .unwrap_or_else(|_| panic!("malformed OTP_ROUTER_FALLBACK specified: `{s}`"))
+            })
+            .unwrap_or(default_upstream_config.fall_back_to_next_otp_router),
+        max_cross_router_gap_meters: std::env::var("OTP_CROSS_ROUTER_MAX_GAP_METERS")
+            .map(|s| {
+                s.parse().unwrap_or_else(|_| {
+                    panic!("malformed OTP_CROSS_ROUTER_MAX_GAP_METERS specified: `{s}`")
+                })
+            })
+            .unwrap_or(default_upstream_config.max_cross_router_gap_meters),
+        pool_max_idle_per_host: std::env::var("UPSTREAM_POOL_MAX_IDLE_PER_HOST")
+            .map(|s| {
+                s.parse().unwrap_or_else(|_| {
+                    panic!("malformed UPSTREAM_POOL_MAX_IDLE_PER_HOST specified: `{s}`")
+                })
+            })
+            .unwrap_or(default_upstream_config.pool_max_idle_per_host),
+        pool_idle_timeout: env_millis("UPSTREAM_POOL_IDLE_TIMEOUT_MS")
+            .unwrap_or(default_upstream_config.pool_idle_timeout),
+        gzip: std::env::var("UPSTREAM_GZIP")
+            .map(|s| {
+                s.parse()
+                    .unwrap_or_else(|_| panic!("malformed UPSTREAM_GZIP specified: `{s}`"))
+            })
+            .unwrap_or(default_upstream_config.gzip),
+        otp_time_zone: std::env::var("OTP_TIME_ZONE")
+            .map(|s| {
+                Some(
+                    s.parse()
+                        .unwrap_or_else(|_| panic!("malformed OTP_TIME_ZONE specified: `{s}`")),
+                )
+            })
+            .unwrap_or(default_upstream_config.otp_time_zone),
+    });
+
+    let default_readiness_config = ReadinessConfig::default();
+    app_state.set_readiness_config(ReadinessConfig {
+        required: std::env::var("READY_REQUIRED_DEPENDENCIES")
+            .map(|s| {
+                DependencyKind::parse_list(&s).unwrap_or_else(|_| {
+                    panic!("malformed READY_REQUIRED_DEPENDENCIES specified: `{s}`")
+                })
+            })
+            .unwrap_or(default_readiness_config.required),
+        probe_timeout: env_millis("READY_PROBE_TIMEOUT_MS")
+            .unwrap_or(default_readiness_config.probe_timeout),
+    });
+
+    if let Ok(s) = std::env::var("OTP_ROUTER_SELECTION") {
+        let selection = s
+            .parse()
+            .unwrap_or_else(|_| panic!("malformed OTP_ROUTER_SELECTION specified: `{s}`"));
+        app_state.set_otp_router_selection(selection);
+    }
+
+    let endpoints: Vec<String> = endpoints.collect();
+    for endpoint in &endpoints {
+        // Unlike an unreachable endpoint, a malformed one will never work, so fail fast
+        let (_api, url) = OtpApi::parse_endpoint(endpoint);
+        if let Err(err) = Url::parse(url) {
+            panic!("Invalid OTP endpoint {endpoint}: {err}")
+        }
+    }
+
     for endpoint in endpoints {
-        // If we change this to be non-blocking, we'll
-        // want to update our readiness probe in health.rs
-        app_state.add_otp_endpoint(&endpoint).await?;
+        // An unreachable endpoint shouldn't take down everything else, so we start without it
+        // and keep retrying in the background. Its status is reported by our readiness probe.
+        if let Err(err) = app_state.add_otp_endpoint(&endpoint).await {
+            log::warn!("starting without OTP endpoint {endpoint}, will retry: {err}");
+        }
     }
 
+    let otp_cluster = app_state.otp_cluster();
     log::info!(
-        "setup completed - there are {} routers.",
-        app_state.otp_cluster().router_len()
+        "setup completed - there are {} routers, {} OTP endpoints pending.",
+        otp_cluster.router_len(),
+        otp_cluster.pending_endpoints().count()
+    );
+
+    app_state.spawn_otp_router_refresh(
+        env_millis("OTP_ROUTER_REFRESH_INTERVAL_MS").unwrap_or(Duration::from_secs(5 * 60)),
+        env_millis("OTP_ENDPOINT_RETRY_INTERVAL_MS").unwrap_or(Duration::from_secs(10)),
     );
 
     let port: u16 = std::env::var("PORT")
@@ -68,10 +160,17 @@ async fn main() -> Result<()> {
         App::new()
             .wrap(Logger::default())
             .app_data(web::Data::new(app_state.clone()))
+            .app_data(api::v6::map_match::payload_config())
             .service(api::v5::plan::get_plan)
             .service(api::v6::plan::get_plan)
             .service(api::v6::directions::get_directions)
             .service(api::v6::elevation::get_elevation)
+            .service(api::v6::coverage::get_coverage)
+            .service(api::v6::isochrone::get_isochrone)
+            .service(api::v6::locate::get_locate)
+            .service(api::v6::map_match::post_match)
+            .service(api::v6::matrix::get_matrix)
+            .service(api::v6::optimized_route::get_optimized_route)
             .service(api::health::get_ready)
             .service(api::health::get_alive)
     })
@@ -81,3 +180,12 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
+
+fn env_millis(name: &str) -> Option<Duration> {
+    std::env::var(name).ok().map(|s| {
+        let millis = s
+            .parse()
+            .unwrap_or_else(|_| panic!("malformed {name} specified: `{s}`"));
+        Duration::from_millis(millis)
+    })
+}
@@ -1,6 +1,7 @@
 use super::{Dem, Result};
 use geo::LineString;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
 
 #[derive(Debug, Clone)]
 pub struct ElevationService {
@@ -21,6 +22,26 @@ impl ElevationService {
             .sample_elevations(line_string, max_sample_meters)
     }
 
+    pub fn tif_dir(&self) -> &Path {
+        &self.tif_dir
+    }
+
+    /// How many elevation tifs are available to sample from.
+    pub fn tif_count(&self) -> io::Result<usize> {
+        let mut count = 0;
+        for entry in fs::read_dir(&self.tif_dir)? {
+            let is_tif = entry?
+                .path()
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("tif"));
+            if is_tif {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
     fn elevation(&self) -> Dem {
         // TODO: pool
         Dem::from_dir(self.tif_dir.clone())
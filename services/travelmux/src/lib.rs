@@ -2,6 +2,8 @@ pub mod api;
 mod elevation;
 mod error;
 pub mod otp;
+#[cfg(test)]
+mod test_util;
 pub mod util;
 pub mod valhalla;
 
@@ -1,10 +1,12 @@
 pub mod otp_api;
+pub mod otp_graphql;
 
 mod otp_cluster;
 use crate::TravelMode;
-pub use otp_cluster::OtpCluster;
+pub use otp_cluster::{OtpCluster, RouterSelection};
 
 mod otp_router;
+pub use otp_router::{OTPRouter, OtpApi, OtpPlanEndpoint};
 
 impl From<otp_api::TransitMode> for TravelMode {
     fn from(mode: otp_api::TransitMode) -> Self {
@@ -16,6 +16,66 @@ pub struct Routers {
     pub router_info: Vec<Router>,
 }
 
+/// Query parameters for a router's `/plan` endpoint, as in:
+///     `plan?fromPlace=47.575837,-122.339414&toPlace=47.651048,-122.347234&mode=TRANSIT&numItineraries=3`
+#[derive(Debug, Serialize, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PlanRequest {
+    /// `lat,lon`
+    pub from_place: String,
+    /// `lat,lon`
+    pub to_place: String,
+    #[serde(serialize_with = "serialize_modes")]
+    pub mode: Vec<RequestMode>,
+    pub num_itineraries: u32,
+    /// Local date, formatted as `YYYY-MM-DD`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date: Option<String>,
+    /// Local time, formatted as `HH:MM`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time: Option<String>,
+    pub arrive_by: bool,
+    pub wheelchair: bool,
+    /// meters per second
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub walk_speed: Option<f64>,
+    /// meters
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_walk_distance: Option<f64>,
+}
+
+/// A mode which can be requested from OTP. Unlike `TransitMode`, this includes modifiers like
+/// `BicyclePark` which describe how a mode is used, but never appear on the legs of a response.
+#[derive(Debug, Serialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum RequestMode {
+    Transit,
+    Walk,
+    Bicycle,
+    /// Ride a bike to a station and park it there
+    BicyclePark,
+    Car,
+    /// Drive to a station and park there
+    CarPark,
+}
+
+fn serialize_modes<S: serde::Serializer>(
+    modes: &[RequestMode],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    use serde::ser::Error;
+    let modes: Vec<String> = modes
+        .iter()
+        .map(|mode| {
+            serde_json::to_value(mode)
+                .ok()
+                .and_then(|value| value.as_str().map(str::to_string))
+                .ok_or_else(|| S::Error::custom(format!("unserializable mode: {mode:?}")))
+        })
+        .collect::<Result<_, _>>()?;
+    serializer.serialize_str(&modes.join(","))
+}
+
 #[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct PlanError {
@@ -28,6 +88,16 @@ pub struct PlanError {
     pub extra: HashMap<String, serde_json::Value>,
 }
 
+impl PlanError {
+    /// The router couldn't find a trip, as opposed to e.g. rejecting the request.
+    pub fn is_no_path(&self) -> bool {
+        matches!(
+            self.message.as_str(),
+            "PATH_NOT_FOUND" | "NO_TRANSIT_TIMES" | "LOCATION_NOT_ACCESSIBLE" | "OUTSIDE_BOUNDS"
+        )
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct PlanResponse {
@@ -86,10 +156,22 @@ pub struct Leg {
     /// Whether there is real-time data about this Leg
     pub real_time: bool,
 
+    /// Offset from UTC, in millis, of the local time in the region served by the router
+    pub agency_time_zone_offset: Option<i64>,
+
     #[serde(flatten)]
     pub extra: HashMap<String, serde_json::Value>,
 }
 
+impl Itinerary {
+    /// Continue this itinerary with the legs of `next`, which should begin where this one ends.
+    pub(crate) fn append(&mut self, next: Itinerary) {
+        self.end_time = next.end_time;
+        self.duration = (self.end_time - self.start_time) / 1000;
+        self.legs.extend(next.legs);
+    }
+}
+
 impl Leg {
     pub(crate) fn duration_seconds(&self) -> f64 {
         (self.end_time - self.start_time) as f64 / 1000.0
@@ -234,6 +316,27 @@ impl From<RelativeDirection> for ValhallaManeuverType {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_plan_request_serialization() {
+        let request = PlanRequest {
+            from_place: "47.575837,-122.339414".to_string(),
+            to_place: "47.651048,-122.347234".to_string(),
+            mode: vec![RequestMode::Transit, RequestMode::Bicycle],
+            num_itineraries: 3,
+            date: Some("2024-05-17".to_string()),
+            time: Some("08:30".to_string()),
+            arrive_by: false,
+            wheelchair: true,
+            walk_speed: None,
+            max_walk_distance: Some(800.0),
+        };
+        let query = serde_urlencoded::to_string(&request).unwrap();
+        assert_eq!(
+            query,
+            "fromPlace=47.575837%2C-122.339414&toPlace=47.651048%2C-122.347234&mode=TRANSIT%2CBICYCLE&numItineraries=3&date=2024-05-17&time=08%3A30&arriveBy=false&wheelchair=true&maxWalkDistance=800.0"
+        );
+    }
+
     #[test]
     fn test_walk_serialization() {
         let mode = TransitMode::Walk;
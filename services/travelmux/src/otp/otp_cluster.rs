@@ -1,54 +1,328 @@
-use crate::otp::otp_router::{OTPRouter, OTPRouterClient};
-use crate::Result;
+use crate::otp::otp_router::{OTPRouter, OTPRouterClient, OtpApi, OtpPlanEndpoint};
+use crate::{Error, Result};
 use geo::geometry::Point;
+use geo::{Area, BoundingRect, Closest, ClosestPoint, Contains, Distance, Haversine};
+use rstar::primitives::{GeomWithData, Rectangle};
+use rstar::RTree;
+use std::str::FromStr;
 use url::Url;
 use wkt::ToWkt;
 
-#[derive(Debug, Clone, PartialEq, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct OtpCluster {
+    /// Every registered endpoint, in the order they were added
+    endpoints: Vec<OtpEndpoint>,
     routers: Vec<OTPRouter>,
+    /// The bounding box of each router's polygon, indexed by its position in `routers`
+    router_index: RTree<GeomWithData<Rectangle<[f64; 2]>, usize>>,
+    selection: RouterSelection,
+}
+
+/// How to choose between multiple routers which all cover a trip, e.g. a metro graph
+/// overlapping a regional one.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum RouterSelection {
+    /// The router from the earliest registered endpoint
+    #[default]
+    FirstMatch,
+    /// The router with the smallest polygon, which is typically the most detailed
+    SmallestPolygon,
+    /// Router ids, most preferred first. Unlisted routers come last, in registration order.
+    Priority(Vec<String>),
+}
+
+impl FromStr for RouterSelection {
+    type Err = Error;
+
+    /// One of `first_match`, `smallest_polygon`, or `priority:<router_id>,<router_id>,...`
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(router_ids) = s.strip_prefix("priority:") {
+            return Ok(Self::Priority(
+                router_ids
+                    .split(',')
+                    .map(|id| id.trim().to_string())
+                    .collect(),
+            ));
+        }
+        match s {
+            "first_match" => Ok(Self::FirstMatch),
+            "smallest_polygon" => Ok(Self::SmallestPolygon),
+            other => Err(Error::server(format!(
+                "unknown router selection: `{other}`"
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct OtpEndpoint {
+    url: Url,
+    api: OtpApi,
+    /// Whether we've ever successfully fetched this endpoint's routers.
+    loaded: bool,
 }
 
 impl OtpCluster {
-    pub async fn insert_endpoint(&mut self, url: Url) -> Result<()> {
-        for router in OTPRouterClient::new(url).fetch_all().await? {
-            self.push_router(router);
+    /// Fetch the routers currently served by `endpoint`, without modifying the cluster.
+    pub async fn fetch_routers(
+        endpoint: &Url,
+        http_client: &reqwest::Client,
+    ) -> Result<Vec<OTPRouter>> {
+        OTPRouterClient::new(endpoint.clone(), http_client.clone())
+            .fetch_all()
+            .await
+    }
+
+    /// Register an endpoint whose routers haven't been fetched yet. It will have no routers
+    /// until `set_endpoint_routers` is called for it.
+    pub fn register_endpoint(&mut self, endpoint: Url, api: OtpApi) {
+        if !self.endpoints.iter().any(|e| e.url == endpoint) {
+            self.endpoints.push(OtpEndpoint {
+                url: endpoint,
+                api,
+                loaded: false,
+            });
         }
-        Ok(())
+    }
+
+    /// Replace any routers previously fetched from `endpoint` with `routers`, registering the
+    /// endpoint if it's new. Routers keep their endpoint's original precedence.
+    pub fn set_endpoint_routers(&mut self, endpoint: Url, routers: Vec<OTPRouter>) {
+        let api = self
+            .endpoints
+            .iter()
+            .find(|registered| registered.url == endpoint)
+            .map(|registered| registered.api)
+            .unwrap_or_default();
+        let routers = routers.into_iter().map(|router| router.with_api(api));
+        let insertion_idx = self
+            .routers
+            .iter()
+            .position(|router| router.endpoint() == &endpoint)
+            .unwrap_or_else(|| {
+                // routers from endpoints registered after this one come after it
+                let later_endpoints = match self.endpoints.iter().position(|e| e.url == endpoint) {
+                    Some(endpoint_idx) => &self.endpoints[endpoint_idx + 1..],
+                    None => &[],
+                };
+                self.routers
+                    .iter()
+                    .position(|router| {
+                        later_endpoints
+                            .iter()
+                            .any(|later| &later.url == router.endpoint())
+                    })
+                    .unwrap_or(self.routers.len())
+            });
+        self.routers.retain(|router| router.endpoint() != &endpoint);
+        let insertion_idx = insertion_idx.min(self.routers.len());
+        self.routers.splice(insertion_idx..insertion_idx, routers);
+        self.reindex_routers();
+
+        self.register_endpoint(endpoint.clone(), api);
+        for registered in &mut self.endpoints {
+            if registered.url == endpoint {
+                registered.loaded = true;
+            }
+        }
+    }
+
+    pub fn endpoints(&self) -> impl Iterator<Item = &Url> {
+        self.endpoints.iter().map(|endpoint| &endpoint.url)
+    }
+
+    /// Endpoints whose routers we've fetched at least once.
+    pub fn loaded_endpoints(&self) -> impl Iterator<Item = &Url> {
+        self.endpoints
+            .iter()
+            .filter(|endpoint| endpoint.loaded)
+            .map(|endpoint| &endpoint.url)
+    }
+
+    /// Endpoints which have been registered, but whose routers we've never managed to fetch.
+    pub fn pending_endpoints(&self) -> impl Iterator<Item = &Url> {
+        self.endpoints
+            .iter()
+            .filter(|endpoint| !endpoint.loaded)
+            .map(|endpoint| &endpoint.url)
     }
 
     pub fn push_router(&mut self, router: OTPRouter) {
-        self.routers.push(router)
+        self.routers.push(router);
+        self.reindex_routers();
+    }
+
+    pub fn set_router_selection(&mut self, selection: RouterSelection) {
+        self.selection = selection;
+    }
+
+    fn reindex_routers(&mut self) {
+        let bounding_boxes = self
+            .routers
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, router)| {
+                let rect = router.polygon().bounding_rect()?;
+                let corners = Rectangle::from_corners(rect.min().into(), rect.max().into());
+                Some(GeomWithData::new(corners, idx))
+            })
+            .collect();
+        self.router_index = RTree::bulk_load(bounding_boxes);
     }
 
+    /// Find a router using OTP's REST API which covers the trip.
     pub fn find_router_url(&self, source: Point, destination: Point) -> Option<Url> {
-        let router = self.find_router(source, destination)?;
-        let router_url = OTPRouterClient::router_url(router);
-        Some(router_url)
+        self.find_routers(&[source, destination])
+            .into_iter()
+            .find(|router| router.api() == OtpApi::Rest)
+            .map(OTPRouterClient::router_url)
     }
 
-    fn find_router(&self, source: Point, destination: Point) -> Option<&OTPRouter> {
-        for router in &self.routers {
-            use geo::algorithm::Contains;
-            if !router.polygon().contains(&source) {
+    /// Like `find_router_url`, but for a trip that must visit each of the `waypoints`.
+    pub fn find_router_url_for_waypoints(&self, waypoints: &[Point]) -> Option<Url> {
+        let router = self.find_routers(waypoints).into_iter().next()?;
+        Some(OTPRouterClient::router_url(router))
+    }
+
+    /// Every router covering all of the `waypoints`, most preferred first.
+    pub fn find_plan_endpoints_for_waypoints(&self, waypoints: &[Point]) -> Vec<OtpPlanEndpoint> {
+        self.find_routers(waypoints)
+            .into_iter()
+            .map(OTPRouterClient::plan_endpoint)
+            .collect()
+    }
+
+    /// Where to request an isochrone around `location`, from the router which covers it.
+    pub fn find_isochrone_endpoint(&self, location: Point) -> Option<OtpPlanEndpoint> {
+        self.find_routers(&[location])
+            .first()
+            .map(|router| OtpPlanEndpoint {
+                url: OTPRouterClient::isochrone_url(router),
+                api: router.api(),
+            })
+    }
+
+    fn find_routers(&self, waypoints: &[Point]) -> Vec<&OTPRouter> {
+        let Some(first_waypoint) = waypoints.first() else {
+            return vec![];
+        };
+        let mut candidates: Vec<usize> = self
+            .router_index
+            .locate_all_at_point(&[first_waypoint.x(), first_waypoint.y()])
+            .map(|bounding_box| bounding_box.data)
+            .filter(|&idx| {
+                let router = &self.routers[idx];
+                waypoints.iter().all(|waypoint| {
+                    let contains = router.polygon().contains(waypoint);
+                    if !contains {
+                        log::debug!(
+                            "trip waypoint isn't within router: ({} NOT WITHIN {})",
+                            waypoint.wkt_string(),
+                            router.polygon().wkt_string()
+                        );
+                    }
+                    contains
+                })
+            })
+            .collect();
+
+        // Registration order breaks any ties, so the selection is deterministic
+        candidates.sort_unstable();
+        match &self.selection {
+            RouterSelection::FirstMatch => {}
+            RouterSelection::SmallestPolygon => candidates.sort_by(|&a, &b| {
+                let area_a = self.routers[a].polygon().unsigned_area();
+                let area_b = self.routers[b].polygon().unsigned_area();
+                area_a.total_cmp(&area_b)
+            }),
+            RouterSelection::Priority(router_ids) => candidates.sort_by_key(|&idx| {
+                router_ids
+                    .iter()
+                    .position(|id| id == self.routers[idx].router_id())
+                    .unwrap_or(router_ids.len())
+            }),
+        }
+        candidates
+            .into_iter()
+            .map(|idx| &self.routers[idx])
+            .collect()
+    }
+
+    /// For a trip which starts in one router but ends in another, find a point just inside the
+    /// origin's router, as close as possible to the destination.
+    ///
+    /// Transit can take the rider as far as this "hub", leaving the rest of the trip to another
+    /// mode, so the destination must be within `max_gap_meters` of the origin's router.
+    /// Returns where to plan with the origin's router, and the hub.
+    pub fn find_cross_router_hub(
+        &self,
+        source: Point,
+        destination: Point,
+        max_gap_meters: f64,
+    ) -> Option<(OtpPlanEndpoint, Point)> {
+        // How far inside the origin's router to place the hub, so that OTP can route to it
+        const HUB_INSET_METERS: f64 = 100.0;
+
+        if !self.find_routers(&[source, destination]).is_empty() {
+            // Not a cross-router trip
+            return None;
+        }
+        if self.find_routers(&[destination]).is_empty() {
+            // The destination has no transit coverage at all
+            return None;
+        }
+
+        self.find_routers(&[source]).into_iter().find_map(|router| {
+            let boundary_point = match router.polygon().exterior().closest_point(&destination) {
+                Closest::Intersection(point) | Closest::SinglePoint(point) => point,
+                Closest::Indeterminate => return None,
+            };
+            if Haversine.distance(boundary_point, destination) > max_gap_meters {
+                // e.g. routers for two distant cities, which no hub could sensibly connect
                 log::debug!(
-                    "trip source isn't within router: ({} NOT WITHIN {})",
-                    source.wkt_string(),
-                    router.polygon().wkt_string()
+                    "destination is too far from router {} for a hub",
+                    router.router_id()
                 );
-                continue;
+                return None;
             }
-            if !router.polygon().contains(&destination) {
-                log::debug!(
-                    "trip destination isn't within router: ({} NOT WITHIN {})",
-                    destination.wkt_string(),
-                    router.polygon().wkt_string()
-                );
-                continue;
+            let distance_to_source = Haversine.distance(boundary_point, source);
+            let inset = f64::min(1.0, HUB_INSET_METERS / distance_to_source);
+            let hub = boundary_point + (source - boundary_point) * inset;
+            if !router.polygon().contains(&hub) {
+                // e.g. a concave polygon, where the nearest edge isn't facing the source
+                log::debug!("no hub for router {}", router.router_id());
+                return None;
             }
-            return Some(router);
-        }
-        None
+            Some((OTPRouterClient::plan_endpoint(router), hub))
+        })
+    }
+
+    /// The router nearest to the `waypoints` - minimizing the distance to the furthest of them -
+    /// along with the distance in meters from each waypoint to the router's polygon.
+    pub fn find_nearest_router(&self, waypoints: &[Point]) -> Option<(&OTPRouter, Vec<f64>)> {
+        self.routers
+            .iter()
+            .filter_map(|router| {
+                let distances = waypoints
+                    .iter()
+                    .map(|waypoint| match router.polygon().closest_point(waypoint) {
+                        Closest::Intersection(point) | Closest::SinglePoint(point) => {
+                            Some(Haversine.distance(point, *waypoint))
+                        }
+                        Closest::Indeterminate => None,
+                    })
+                    .collect::<Option<Vec<f64>>>()?;
+                Some((router, distances))
+            })
+            .min_by(|(_, a), (_, b)| {
+                let furthest_a = a.iter().copied().fold(0.0, f64::max);
+                let furthest_b = b.iter().copied().fold(0.0, f64::max);
+                furthest_a.total_cmp(&furthest_b)
+            })
+    }
+
+    pub fn routers(&self) -> impl Iterator<Item = &OTPRouter> {
+        self.routers.iter()
     }
 
     pub fn router_len(&self) -> usize {
@@ -137,5 +411,280 @@ mod test {
             let result = cluster.find_router_url(p1_a, p2_b);
             assert_eq!(result, None);
         }
+
+        // every waypoint covered by the same router
+        {
+            let result = cluster
+                .find_router_url_for_waypoints(&[p1_a, p1_b, p1_a])
+                .expect("should have found a result");
+            let expected = Url::parse("http://host_1.example.com/foo/router_1/plan").unwrap();
+            assert_eq!(result, expected);
+        }
+
+        // an intermediate waypoint outside of the router
+        {
+            let result = cluster.find_router_url_for_waypoints(&[p1_a, p2_a, p1_b]);
+            assert_eq!(result, None);
+        }
+    }
+
+    #[test]
+    fn replace_endpoint_routers() {
+        use wkt::TryFromWkt;
+
+        let endpoint_1 = Url::parse("http://host_1.example.com/foo").unwrap();
+        let endpoint_2 = Url::parse("http://host_2.example.com/foo").unwrap();
+        let polygon = Polygon::try_from_wkt_str("POLYGON ((0 0, 40 0, 40 40, 0 40, 0 0))").unwrap();
+        let router = |endpoint: &Url, router_id: &str| {
+            OTPRouter::new(endpoint.clone(), router_id.to_string(), polygon.clone())
+        };
+
+        let mut cluster = OtpCluster::default();
+        cluster.set_endpoint_routers(endpoint_1.clone(), vec![router(&endpoint_1, "old")]);
+        cluster.set_endpoint_routers(endpoint_2.clone(), vec![router(&endpoint_2, "other")]);
+        assert_eq!(cluster.router_len(), 2);
+        assert_eq!(
+            cluster.endpoints().collect::<Vec<_>>(),
+            vec![&endpoint_1, &endpoint_2]
+        );
+
+        let p1 = Point::new(1.0, 1.0);
+        let p2 = Point::new(2.0, 2.0);
+        assert_eq!(
+            cluster.find_router_url(p1, p2).unwrap().as_str(),
+            "http://host_1.example.com/foo/old/plan"
+        );
+
+        // Refreshed routers replace the stale ones, retaining their precedence
+        cluster.set_endpoint_routers(endpoint_1.clone(), vec![router(&endpoint_1, "new")]);
+        assert_eq!(cluster.router_len(), 2);
+        assert_eq!(
+            cluster.find_router_url(p1, p2).unwrap().as_str(),
+            "http://host_1.example.com/foo/new/plan"
+        );
+
+        // An endpoint with no routers stays registered
+        cluster.set_endpoint_routers(endpoint_1.clone(), vec![]);
+        assert_eq!(cluster.router_len(), 1);
+        assert_eq!(cluster.endpoints().count(), 2);
+        assert_eq!(
+            cluster.find_router_url(p1, p2).unwrap().as_str(),
+            "http://host_2.example.com/foo/other/plan"
+        );
+
+        // ...and regains its precedence when its routers return
+        cluster.set_endpoint_routers(endpoint_1.clone(), vec![router(&endpoint_1, "back")]);
+        assert_eq!(
+            cluster.find_router_url(p1, p2).unwrap().as_str(),
+            "http://host_1.example.com/foo/back/plan"
+        );
+    }
+
+    #[test]
+    fn pending_endpoints() {
+        use wkt::TryFromWkt;
+
+        let endpoint_1 = Url::parse("http://host_1.example.com/foo").unwrap();
+        let endpoint_2 = Url::parse("http://host_2.example.com/foo").unwrap();
+        let polygon = Polygon::try_from_wkt_str("POLYGON ((0 0, 40 0, 40 40, 0 40, 0 0))").unwrap();
+
+        let mut cluster = OtpCluster::default();
+        cluster.register_endpoint(endpoint_1.clone(), OtpApi::Rest);
+        cluster.register_endpoint(endpoint_2.clone(), OtpApi::Rest);
+        assert_eq!(cluster.loaded_endpoints().count(), 0);
+        assert_eq!(cluster.pending_endpoints().count(), 2);
+
+        // A loaded endpoint with no routers isn't pending
+        cluster.set_endpoint_routers(endpoint_1.clone(), vec![]);
+        assert_eq!(
+            cluster.loaded_endpoints().collect::<Vec<_>>(),
+            vec![&endpoint_1]
+        );
+        assert_eq!(
+            cluster.pending_endpoints().collect::<Vec<_>>(),
+            vec![&endpoint_2]
+        );
+
+        // A late-loading endpoint keeps its registration order
+        cluster.set_endpoint_routers(
+            endpoint_2.clone(),
+            vec![OTPRouter::new(
+                endpoint_2.clone(),
+                "router".to_string(),
+                polygon,
+            )],
+        );
+        assert_eq!(cluster.pending_endpoints().count(), 0);
+        assert_eq!(
+            cluster.endpoints().collect::<Vec<_>>(),
+            vec![&endpoint_1, &endpoint_2]
+        );
+        assert_eq!(cluster.router_len(), 1);
+    }
+
+    #[test]
+    fn overlapping_routers() {
+        use wkt::TryFromWkt;
+
+        let endpoint = Url::parse("http://host.example.com/foo").unwrap();
+        let router = |router_id: &str, wkt: &str| {
+            OTPRouter::new(
+                endpoint.clone(),
+                router_id.to_string(),
+                Polygon::try_from_wkt_str(wkt).unwrap(),
+            )
+        };
+        let mut cluster = OtpCluster::default();
+        cluster.push_router(router(
+            "regional",
+            "POLYGON ((0 0, 100 0, 100 100, 0 100, 0 0))",
+        ));
+        cluster.push_router(router(
+            "metro",
+            "POLYGON ((10 10, 20 10, 20 20, 10 20, 10 10))",
+        ));
+        cluster.push_router(router(
+            "elsewhere",
+            "POLYGON ((200 0, 210 0, 210 10, 200 10, 200 0))",
+        ));
+
+        let in_metro = [Point::new(11.0, 11.0), Point::new(12.0, 12.0)];
+        let router_ids = |cluster: &OtpCluster, waypoints: &[Point]| -> Vec<String> {
+            cluster
+                .find_routers(waypoints)
+                .iter()
+                .map(|router| router.router_id().to_string())
+                .collect()
+        };
+
+        assert_eq!(router_ids(&cluster, &in_metro), vec!["regional", "metro"]);
+
+        cluster.set_router_selection(RouterSelection::SmallestPolygon);
+        assert_eq!(router_ids(&cluster, &in_metro), vec!["metro", "regional"]);
+        assert_eq!(
+            cluster
+                .find_router_url_for_waypoints(&in_metro)
+                .unwrap()
+                .as_str(),
+            "http://host.example.com/foo/metro/plan"
+        );
+        // Only the regional router covers a trip leaving the metro area
+        assert_eq!(
+            router_ids(&cluster, &[Point::new(11.0, 11.0), Point::new(50.0, 50.0)]),
+            vec!["regional"]
+        );
+
+        cluster.set_router_selection(RouterSelection::Priority(vec!["metro".to_string()]));
+        assert_eq!(router_ids(&cluster, &in_metro), vec!["metro", "regional"]);
+        cluster.set_router_selection(RouterSelection::Priority(vec![
+            "elsewhere".to_string(),
+            "regional".to_string(),
+        ]));
+        assert_eq!(router_ids(&cluster, &in_metro), vec!["regional", "metro"]);
+    }
+
+    #[test]
+    fn parse_router_selection() {
+        assert_eq!(
+            "first_match".parse::<RouterSelection>().unwrap(),
+            RouterSelection::FirstMatch
+        );
+        assert_eq!(
+            "smallest_polygon".parse::<RouterSelection>().unwrap(),
+            RouterSelection::SmallestPolygon
+        );
+        assert_eq!(
+            "priority:metro, regional"
+                .parse::<RouterSelection>()
+                .unwrap(),
+            RouterSelection::Priority(vec!["metro".to_string(), "regional".to_string()])
+        );
+        assert!("largest".parse::<RouterSelection>().is_err());
+    }
+
+    #[test]
+    fn cross_router_hub() {
+        use approx::assert_relative_eq;
+        use wkt::TryFromWkt;
+
+        let endpoint = Url::parse("http://host.example.com/foo").unwrap();
+        let mut cluster = OtpCluster::default();
+        cluster.push_router(OTPRouter::new(
+            endpoint.clone(),
+            "west".to_string(),
+            Polygon::try_from_wkt_str("POLYGON ((0 0, 1 0, 1 1, 0 1, 0 0))").unwrap(),
+        ));
+        cluster.push_router(OTPRouter::new(
+            endpoint.clone(),
+            "east".to_string(),
+            Polygon::try_from_wkt_str("POLYGON ((2 0, 3 0, 3 1, 2 1, 2 0))").unwrap(),
+        ));
+
+        let in_west = Point::new(0.5, 0.5);
+        let in_east = Point::new(2.5, 0.5);
+        // The destination is a degree and a half of longitude, about 167km, from the west router
+        let max_gap_meters = 200_000.0;
+        let (plan_endpoint, hub) = cluster
+            .find_cross_router_hub(in_west, in_east, max_gap_meters)
+            .expect("trip spans two routers");
+        assert_eq!(
+            plan_endpoint.url.as_str(),
+            "http://host.example.com/foo/west/plan"
+        );
+        // Just inside the east edge of the western router
+        assert_relative_eq!(hub.y(), 0.5);
+        assert!(hub.x() < 1.0 && hub.x() > 0.99, "{hub:?}");
+
+        // Within a single router
+        assert_eq!(
+            cluster.find_cross_router_hub(in_west, Point::new(0.6, 0.6), max_gap_meters),
+            None
+        );
+        // Destination without any coverage
+        assert_eq!(
+            cluster.find_cross_router_hub(in_west, Point::new(1.5, 0.5), max_gap_meters),
+            None
+        );
+        // Routers too far apart to connect
+        assert_eq!(
+            cluster.find_cross_router_hub(in_west, in_east, 10_000.0),
+            None
+        );
+    }
+
+    #[test]
+    fn nearest_router() {
+        use approx::assert_relative_eq;
+        use wkt::TryFromWkt;
+
+        let endpoint = Url::parse("http://host.example.com/foo").unwrap();
+        let mut cluster = OtpCluster::default();
+        assert!(cluster
+            .find_nearest_router(&[Point::new(0.0, 0.0)])
+            .is_none());
+
+        cluster.push_router(OTPRouter::new(
+            endpoint.clone(),
+            "near".to_string(),
+            Polygon::try_from_wkt_str("POLYGON ((0 0, 1 0, 1 1, 0 1, 0 0))").unwrap(),
+        ));
+        cluster.push_router(OTPRouter::new(
+            endpoint.clone(),
+            "far".to_string(),
+            Polygon::try_from_wkt_str("POLYGON ((10 0, 11 0, 11 1, 10 1, 10 0))").unwrap(),
+        ));
+
+        let inside = Point::new(0.5, 0.5);
+        let outside = Point::new(2.0, 0.5);
+        let (router, distances) = cluster.find_nearest_router(&[inside, outside]).unwrap();
+        assert_eq!(router.router_id(), "near");
+        assert_eq!(distances[0], 0.0);
+        // about one degree of longitude at the equator
+        assert_relative_eq!(distances[1], 111_195.0, epsilon = 100.0);
+
+        let (router, _) = cluster
+            .find_nearest_router(&[Point::new(9.0, 0.5)])
+            .unwrap();
+        assert_eq!(router.router_id(), "far");
     }
 }
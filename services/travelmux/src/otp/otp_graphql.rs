@@ -0,0 +1,845 @@
+//! OTP2's GTFS GraphQL API (`planConnection`), which replaces the deprecated REST `/plan`.
+//!
+//! Responses are converted into the REST shapes in `otp_api`, so the rest of travelmux needn't
+//! care which API planned a trip.
+
+use crate::otp::otp_api::{
+    self, AbsoluteDirection, LegGeometry, PlanError, PlanRequest, RelativeDirection, RequestMode,
+    TransitMode,
+};
+use crate::{Error, Result};
+use chrono::{DateTime, FixedOffset, NaiveDateTime};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+const PLAN_CONNECTION_QUERY: &str = r#"
+query PlanConnection(
+  $origin: PlanLabeledLocationInput!,
+  $destination: PlanLabeledLocationInput!,
+  $first: Int,
+  $dateTime: PlanDateTimeInput,
+  $modes: PlanModesInput,
+  $preferences: PlanPreferencesInput
+) {
+  planConnection(
+    origin: $origin,
+    destination: $destination,
+    first: $first,
+    dateTime: $dateTime,
+    modes: $modes,
+    preferences: $preferences
+  ) {
+    routingErrors { code description }
+    edges {
+      node {
+        start
+        end
+        legs {
+          mode
+          distance
+          realTime
+          transitLeg
+          headsign
+          start { scheduledTime estimated { time } }
+          end { scheduledTime estimated { time } }
+          from { ...place }
+          to { ...place }
+          legGeometry { length points }
+          route { gtfsId shortName longName color textColor }
+          agency { gtfsId name }
+          trip { gtfsId }
+          steps {
+            distance
+            relativeDirection
+            absoluteDirection
+            streetName
+            exit
+            stayOn
+            area
+            bogusName
+            lon
+            lat
+          }
+        }
+      }
+    }
+  }
+}
+
+fragment place on Place {
+  name
+  lat
+  lon
+  arrival { scheduledTime estimated { time } }
+  departure { scheduledTime estimated { time } }
+  stop { gtfsId code }
+}
+"#;
+
+const AGENCY_TIME_ZONES_QUERY: &str = r#"
+query AgencyTimeZones {
+  agencies { timezone }
+}
+"#;
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct GraphQlRequest {
+    query: &'static str,
+    variables: Value,
+}
+
+/// Build a `planConnection` query equivalent to the REST `plan_request`.
+///
+/// OTP's GraphQL API needs absolute times, so a requested `date` and `time` can only be
+/// expressed given the router's `time_zone`.
+pub fn plan_connection_request(
+    plan_request: &PlanRequest,
+    time_zone: Option<Tz>,
+) -> Result<GraphQlRequest> {
+    if plan_request.max_walk_distance.is_some() {
+        return Err(Error::user(
+            "maxWalkDistance is not supported by this trip's OTP router",
+        ));
+    }
+
+    let mut variables = json!({
+        "origin": location_input(&plan_request.from_place)?,
+        "destination": location_input(&plan_request.to_place)?,
+        "first": plan_request.num_itineraries,
+        "modes": modes_input(&plan_request.mode),
+        "preferences": preferences_input(plan_request),
+    });
+
+    if let (Some(date), Some(time)) = (&plan_request.date, &plan_request.time) {
+        let Some(time_zone) = time_zone else {
+            return Err(Error::server(
+                "unknown time zone for OTP router, can't plan for the requested time",
+            ));
+        };
+        let local_time = NaiveDateTime::parse_from_str(&format!("{date} {time}"), "%Y-%m-%d %H:%M")
+            .map_err(|e| Error::server(format!("invalid date and time for OTP: {e}")))?;
+        // When clocks go back, an hour repeats, so take its first occurrence.
+        let Some(date_time) = local_time.and_local_timezone(time_zone).earliest() else {
+            // When clocks go forward, an hour is skipped
+            return Err(Error::user(format!(
+                "{date} {time} doesn't exist in {time_zone}"
+            )));
+        };
+        let date_time = date_time.to_rfc3339();
+        variables["dateTime"] = if plan_request.arrive_by {
+            json!({ "latestArrival": date_time })
+        } else {
+            json!({ "earliestDeparture": date_time })
+        };
+    }
+
+    Ok(GraphQlRequest {
+        query: PLAN_CONNECTION_QUERY,
+        variables,
+    })
+}
+
+/// Ask for the time zones of a router's transit agencies.
+pub fn agency_time_zones_request() -> GraphQlRequest {
+    GraphQlRequest {
+        query: AGENCY_TIME_ZONES_QUERY,
+        variables: json!({}),
+    }
+}
+
+/// `location` is formatted as "lat,lon"
+fn location_input(location: &str) -> Result<Value> {
+    let coordinates = location
+        .split_once(',')
+        .and_then(|(lat, lon)| Some((lat.parse::<f64>().ok()?, lon.parse::<f64>().ok()?)));
+    let Some((latitude, longitude)) = coordinates else {
+        return Err(Error::server(format!("invalid OTP location: {location:?}")));
+    };
+    Ok(json!({
+        "location": { "coordinate": { "latitude": latitude, "longitude": longitude } }
+    }))
+}
+
+fn modes_input(modes: &[RequestMode]) -> Value {
+    if !modes.contains(&RequestMode::Transit) {
+        let direct = match modes.first() {
+            Some(RequestMode::Bicycle) => "BICYCLE",
+            Some(RequestMode::Car) => "CAR",
+            _ => "WALK",
+        };
+        return json!({ "directOnly": true, "direct": [direct] });
+    }
+
+    let (access, egress, transfer, direct) = if modes.contains(&RequestMode::Bicycle) {
+        ("BICYCLE", "BICYCLE", "BICYCLE", "BICYCLE")
+    } else if modes.contains(&RequestMode::BicyclePark) {
+        ("BICYCLE_PARKING", "WALK", "WALK", "BICYCLE")
+    } else if modes.contains(&RequestMode::CarPark) {
+        ("CAR_PARKING", "WALK", "WALK", "CAR")
+    } else {
+        ("WALK", "WALK", "WALK", "WALK")
+    };
+    json!({
+        "direct": [direct],
+        "transit": {
+            "access": [access],
+            "egress": [egress],
+            "transfer": [transfer],
+        }
+    })
+}
+
+fn preferences_input(plan_request: &PlanRequest) -> Value {
+    let mut preferences = json!({
+        "accessibility": { "wheelchair": { "enabled": plan_request.wheelchair } }
+    });
+    if let Some(walk_speed) = plan_request.walk_speed {
+        preferences["street"] = json!({ "walk": { "speed": walk_speed } });
+    }
+    preferences
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GraphQlResponse {
+    data: Option<GraphQlData>,
+    #[serde(default)]
+    errors: Vec<GraphQlError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphQlError {
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AgencyTimeZonesResponse {
+    data: Option<AgencyTimeZonesData>,
+    #[serde(default)]
+    errors: Vec<GraphQlError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AgencyTimeZonesData {
+    agencies: Vec<AgencyTimeZone>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AgencyTimeZone {
+    timezone: String,
+}
+
+impl AgencyTimeZonesResponse {
+    /// The router's time zone, which GTFS requires to be shared by all of a feed's agencies.
+    pub fn into_time_zone(self) -> Result<Tz> {
+        if !self.errors.is_empty() {
+            let messages: Vec<_> = self.errors.into_iter().map(|e| e.message).collect();
+            return Err(Error::server(format!(
+                "OTP GraphQL errors: {}",
+                messages.join("; ")
+            )));
+        }
+        let Some(data) = self.data else {
+            return Err(Error::server("OTP GraphQL response had no data"));
+        };
+        let mut time_zones = data.agencies.into_iter().map(|agency| {
+            agency.timezone.parse::<Tz>().map_err(|e| {
+                Error::server(format!(
+                    "invalid agency timezone {:?}: {e}",
+                    agency.timezone
+                ))
+            })
+        });
+        let Some(time_zone) = time_zones.next().transpose()? else {
+            return Err(Error::server("OTP router has no agencies"));
+        };
+        for other in time_zones {
+            if other? != time_zone {
+                log::warn!("OTP router's agencies have different time zones, using {time_zone}");
+                break;
+            }
+        }
+        Ok(time_zone)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphQlData {
+    plan_connection: PlanConnection,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PlanConnection {
+    #[serde(default)]
+    routing_errors: Vec<RoutingError>,
+    edges: Vec<Edge>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RoutingError {
+    code: String,
+    description: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Edge {
+    node: Itinerary,
+}
+
+#[derive(Debug, Deserialize)]
+struct Itinerary {
+    start: String,
+    end: String,
+    legs: Vec<Leg>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Leg {
+    mode: String,
+    distance: f64,
+    real_time: bool,
+    transit_leg: bool,
+    headsign: Option<String>,
+    start: LegTime,
+    end: LegTime,
+    from: Place,
+    to: Place,
+    leg_geometry: Option<LegGeometry>,
+    route: Option<Route>,
+    agency: Option<Agency>,
+    trip: Option<Trip>,
+    #[serde(default)]
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LegTime {
+    scheduled_time: String,
+    estimated: Option<EstimatedTime>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EstimatedTime {
+    time: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Place {
+    name: Option<String>,
+    lat: f64,
+    lon: f64,
+    arrival: Option<LegTime>,
+    departure: Option<LegTime>,
+    stop: Option<Stop>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Stop {
+    gtfs_id: String,
+    code: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Route {
+    gtfs_id: String,
+    short_name: Option<String>,
+    long_name: Option<String>,
+    color: Option<String>,
+    text_color: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Agency {
+    gtfs_id: String,
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Trip {
+    gtfs_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Step {
+    distance: f64,
+    relative_direction: Option<String>,
+    absolute_direction: Option<AbsoluteDirection>,
+    street_name: Option<String>,
+    exit: Option<String>,
+    stay_on: Option<bool>,
+    area: Option<bool>,
+    bogus_name: Option<bool>,
+    lon: f64,
+    lat: f64,
+}
+
+impl LegTime {
+    /// The real-time estimate, if there is one.
+    fn time(&self) -> &str {
+        match &self.estimated {
+            Some(estimated) => &estimated.time,
+            None => &self.scheduled_time,
+        }
+    }
+}
+
+fn parse_time(time: &str) -> Result<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc3339(time)
+        .map_err(|e| Error::server(format!("invalid time from OTP: {time:?}: {e}")))
+}
+
+fn parse_millis(time: &str) -> Result<u64> {
+    Ok(parse_time(time)?.timestamp_millis() as u64)
+}
+
+/// GraphQL has a few more modes than the REST API - approximate them with their nearest
+/// REST equivalent.
+fn transit_mode(mode: &str) -> TransitMode {
+    match serde_json::from_value(Value::from(mode)) {
+        Ok(mode) => mode,
+        Err(_) => match mode {
+            "COACH" | "TROLLEYBUS" => TransitMode::Bus,
+            "MONORAIL" => TransitMode::Rail,
+            "SCOOTER" => TransitMode::Bicycle,
+            "TAXI" | "CARPOOL" => TransitMode::Car,
+            _ => TransitMode::Transit,
+        },
+    }
+}
+
+/// GraphQL has a few more directions than the REST API, e.g. `ENTER_STATION`.
+fn relative_direction(direction: Option<&str>) -> RelativeDirection {
+    direction
+        .and_then(|direction| serde_json::from_value(Value::from(direction)).ok())
+        .unwrap_or(RelativeDirection::Continue)
+}
+
+/// The equivalent REST API error, as `(id, message)`
+fn rest_error(code: &str) -> (u32, &'static str) {
+    match code {
+        "OUTSIDE_BOUNDS" => (400, "OUTSIDE_BOUNDS"),
+        "OUTSIDE_SERVICE_PERIOD" => (406, "NO_TRANSIT_TIMES"),
+        "WALKING_BETTER_THAN_TRANSIT" => (409, "TOO_CLOSE"),
+        "LOCATION_NOT_FOUND" | "NO_STOPS_IN_RANGE" => (470, "LOCATION_NOT_ACCESSIBLE"),
+        _ => (404, "PATH_NOT_FOUND"),
+    }
+}
+
+impl GraphQlResponse {
+    /// Convert into the equivalent REST API response.
+    pub fn into_plan_response(self) -> Result<otp_api::PlanResponse> {
+        if !self.errors.is_empty() {
+            let messages: Vec<_> = self.errors.into_iter().map(|e| e.message).collect();
+            return Err(Error::server(format!(
+                "OTP GraphQL errors: {}",
+                messages.join("; ")
+            )));
+        }
+        let Some(data) = self.data else {
+            return Err(Error::server("OTP GraphQL response had no data"));
+        };
+        let plan_connection = data.plan_connection;
+
+        let itineraries = plan_connection
+            .edges
+            .into_iter()
+            .map(|edge| edge.node.into_rest())
+            .collect::<Result<Vec<_>>>()?;
+
+        let error = match plan_connection.routing_errors.first() {
+            Some(routing_error) if itineraries.is_empty() => {
+                let (id, message) = rest_error(&routing_error.code);
+                Some(PlanError {
+                    id,
+                    msg: routing_error.description.clone(),
+                    message: message.to_string(),
+                    extra: HashMap::new(),
+                })
+            }
+            _ => None,
+        };
+
+        Ok(otp_api::PlanResponse {
+            plan: otp_api::Plan {
+                itineraries,
+                extra: HashMap::new(),
+            },
+            error,
+            extra: HashMap::new(),
+        })
+    }
+}
+
+impl Itinerary {
+    fn into_rest(self) -> Result<otp_api::Itinerary> {
+        let start_time = parse_millis(&self.start)?;
+        let end_time = parse_millis(&self.end)?;
+        Ok(otp_api::Itinerary {
+            duration: end_time.saturating_sub(start_time) / 1000,
+            legs: self
+                .legs
+                .into_iter()
+                .map(Leg::into_rest)
+                .collect::<Result<_>>()?,
+            start_time,
+            end_time,
+            extra: HashMap::new(),
+        })
+    }
+}
+
+impl Leg {
+    fn into_rest(self) -> Result<otp_api::Leg> {
+        let start = parse_time(self.start.time())?;
+        let end = parse_time(self.end.time())?;
+
+        // Fields which travelmux doesn't use, but which clients of the REST API expect
+        let mut extra = HashMap::new();
+        extra.insert("transitLeg".to_string(), Value::from(self.transit_leg));
+        extra.insert("headsign".to_string(), Value::from(self.headsign));
+        extra.insert(
+            "duration".to_string(),
+            Value::from((end - start).num_milliseconds() as f64 / 1000.0),
+        );
+        if let Some(route) = &self.route {
+            extra.insert("routeId".to_string(), Value::from(route.gtfs_id.clone()));
+            extra.insert(
+                "routeShortName".to_string(),
+                Value::from(route.short_name.clone()),
+            );
+            extra.insert(
+                "routeLongName".to_string(),
+                Value::from(route.long_name.clone()),
+            );
+            extra.insert(
+                "routeTextColor".to_string(),
+                Value::from(route.text_color.clone()),
+            );
+        }
+        if let Some(agency) = self.agency {
+            extra.insert("agencyId".to_string(), Value::from(agency.gtfs_id));
+            extra.insert("agencyName".to_string(), Value::from(agency.name));
+        }
+        if let Some(trip) = self.trip {
+            extra.insert("tripId".to_string(), Value::from(trip.gtfs_id));
+        }
+
+        let leg_geometry = self.leg_geometry.unwrap_or(LegGeometry {
+            length: 0.0,
+            points: String::new(),
+        });
+        Ok(otp_api::Leg {
+            mode: transit_mode(&self.mode),
+            distance: self.distance,
+            leg_geometry,
+            route_color: self.route.and_then(|route| route.color),
+            steps: self.steps.into_iter().map(Step::into_rest).collect(),
+            from: self.from.into_rest()?,
+            to: self.to.into_rest()?,
+            start_time: start.timestamp_millis() as u64,
+            end_time: end.timestamp_millis() as u64,
+            real_time: self.real_time,
+            agency_time_zone_offset: Some(start.offset().local_minus_utc() as i64 * 1000),
+            extra,
+        })
+    }
+}
+
+impl Place {
+    fn into_rest(self) -> Result<otp_api::Place> {
+        let mut extra = HashMap::new();
+        if let Some(stop) = self.stop {
+            extra.insert("stopId".to_string(), Value::from(stop.gtfs_id));
+            extra.insert("stopCode".to_string(), Value::from(stop.code));
+        }
+        Ok(otp_api::Place {
+            location: otp_api::LonLat {
+                lat: self.lat,
+                lon: self.lon,
+            },
+            arrival: self
+                .arrival
+                .map(|arrival| parse_millis(arrival.time()))
+                .transpose()?,
+            departure: self
+                .departure
+                .map(|departure| parse_millis(departure.time()))
+                .transpose()?,
+            name: self.name,
+            extra,
+        })
+    }
+}
+
+impl Step {
+    fn into_rest(self) -> otp_api::Step {
+        otp_api::Step {
+            distance: self.distance,
+            relative_direction: relative_direction(self.relative_direction.as_deref()),
+            street_name: self.street_name.unwrap_or_default(),
+            absolute_direction: self.absolute_direction,
+            exit: self.exit,
+            stay_on: self.stay_on,
+            area: self.area,
+            bogus_name: self.bogus_name,
+            lon: self.lon,
+            lat: self.lat,
+            extra: HashMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorType;
+
+    fn plan_request() -> PlanRequest {
+        PlanRequest {
+            from_place: "47.575837,-122.339414".to_string(),
+            to_place: "47.651048,-122.347234".to_string(),
+            mode: vec![RequestMode::Transit, RequestMode::Walk],
+            num_itineraries: 3,
+            date: Some("2024-05-17".to_string()),
+            time: Some("08:30".to_string()),
+            arrive_by: false,
+            wheelchair: false,
+            walk_speed: Some(1.2),
+            max_walk_distance: None,
+        }
+    }
+
+    #[test]
+    fn build_plan_connection_request() {
+        let time_zone = Some(chrono_tz::America::Los_Angeles);
+        let request = plan_connection_request(&plan_request(), time_zone).unwrap();
+        assert_eq!(
+            request.variables,
+            json!({
+                "origin": { "location": { "coordinate": { "latitude": 47.575837, "longitude": -122.339414 } } },
+                "destination": { "location": { "coordinate": { "latitude": 47.651048, "longitude": -122.347234 } } },
+                "first": 3,
+                "dateTime": { "earliestDeparture": "2024-05-17T08:30:00-07:00" },
+                "modes": {
+                    "direct": ["WALK"],
+                    "transit": { "access": ["WALK"], "egress": ["WALK"], "transfer": ["WALK"] }
+                },
+                "preferences": {
+                    "accessibility": { "wheelchair": { "enabled": false } },
+                    "street": { "walk": { "speed": 1.2 } }
+                }
+            })
+        );
+
+        // Without knowing the router's time zone, we can't request a time
+        let err = plan_connection_request(&plan_request(), None).unwrap_err();
+        assert_eq!(err.error_type, ErrorType::Server);
+
+        // Offsets follow daylight saving time for the requested date
+        let winter = PlanRequest {
+            date: Some("2024-12-17".to_string()),
+            ..plan_request()
+        };
+        let request = plan_connection_request(&winter, time_zone).unwrap();
+        assert_eq!(
+            request.variables["dateTime"],
+            json!({ "earliestDeparture": "2024-12-17T08:30:00-08:00" })
+        );
+        let skipped = PlanRequest {
+            date: Some("2024-03-10".to_string()),
+            time: Some("02:30".to_string()),
+            ..plan_request()
+        };
+        let err = plan_connection_request(&skipped, time_zone).unwrap_err();
+        assert_eq!(err.error_type, ErrorType::User);
+
+        let max_walk_distance = PlanRequest {
+            max_walk_distance: Some(800.0),
+            ..plan_request()
+        };
+        let err = plan_connection_request(&max_walk_distance, time_zone).unwrap_err();
+        assert_eq!(err.error_type, ErrorType::User);
+
+        let arrive_by = PlanRequest {
+            arrive_by: true,
+            mode: vec![RequestMode::Transit, RequestMode::CarPark],
+            ..plan_request()
+        };
+        let request = plan_connection_request(&arrive_by, time_zone).unwrap();
+        assert_eq!(
+            request.variables["dateTime"],
+            json!({ "latestArrival": "2024-05-17T08:30:00-07:00" })
+        );
+        assert_eq!(
+            request.variables["modes"]["transit"]["access"],
+            json!(["CAR_PARKING"])
+        );
+
+        // Planning from now doesn't need a time zone
+        let bicycle = PlanRequest {
+            mode: vec![RequestMode::Bicycle],
+            date: None,
+            time: None,
+            ..plan_request()
+        };
+        let request = plan_connection_request(&bicycle, None).unwrap();
+        assert!(request.variables.get("dateTime").is_none());
+        assert_eq!(
+            request.variables["modes"],
+            json!({ "directOnly": true, "direct": ["BICYCLE"] })
+        );
+    }
+
+    #[test]
+    fn agency_time_zone() {
+        let response: AgencyTimeZonesResponse = serde_json::from_value(json!({
+            "data": { "agencies": [
+                { "timezone": "America/Los_Angeles" },
+                { "timezone": "America/Los_Angeles" }
+            ] }
+        }))
+        .unwrap();
+        assert_eq!(
+            response.into_time_zone().unwrap(),
+            chrono_tz::America::Los_Angeles
+        );
+
+        for bad in [
+            json!({ "data": { "agencies": [] } }),
+            json!({ "data": { "agencies": [{ "timezone": "Pacific Time" }] } }),
+            json!({ "data": null, "errors": [{ "message": "boom" }] }),
+        ] {
+            let response: AgencyTimeZonesResponse = serde_json::from_value(bad).unwrap();
+            assert!(response.into_time_zone().is_err());
+        }
+    }
+
+    #[test]
+    fn convert_plan_connection_response() {
+        let response: GraphQlResponse = serde_json::from_value(json!({
+            "data": {
+                "planConnection": {
+                    "routingErrors": [],
+                    "edges": [{
+                        "node": {
+                            "start": "2024-05-17T08:31:00-07:00",
+                            "end": "2024-05-17T08:50:00-07:00",
+                            "legs": [{
+                                "mode": "WALK",
+                                "distance": 250.5,
+                                "realTime": false,
+                                "transitLeg": false,
+                                "headsign": null,
+                                "start": { "scheduledTime": "2024-05-17T08:31:00-07:00", "estimated": null },
+                                "end": { "scheduledTime": "2024-05-17T08:35:00-07:00", "estimated": null },
+                                "from": { "name": "Origin", "lat": 47.575837, "lon": -122.339414, "arrival": null, "departure": null, "stop": null },
+                                "to": {
+                                    "name": "3rd Ave & Pine St",
+                                    "lat": 47.61,
+                                    "lon": -122.34,
+                                    "arrival": { "scheduledTime": "2024-05-17T08:35:00-07:00", "estimated": null },
+                                    "departure": { "scheduledTime": "2024-05-17T08:36:00-07:00", "estimated": null },
+                                    "stop": { "gtfsId": "1:578", "code": "578" }
+                                },
+                                "legGeometry": { "length": 2, "points": "_p~iF~ps|U_ulLnnqC" },
+                                "route": null,
+                                "agency": null,
+                                "trip": null,
+                                "steps": [{
+                                    "distance": 250.5,
+                                    "relativeDirection": "ENTER_STATION",
+                                    "absoluteDirection": "NORTH",
+                                    "streetName": "3rd Avenue",
+                                    "exit": null,
+                                    "stayOn": false,
+                                    "area": false,
+                                    "bogusName": false,
+                                    "lon": -122.339414,
+                                    "lat": 47.575837
+                                }]
+                            }, {
+                                "mode": "TROLLEYBUS",
+                                "distance": 4000.0,
+                                "realTime": true,
+                                "transitLeg": true,
+                                "headsign": "Downtown",
+                                "start": { "scheduledTime": "2024-05-17T08:36:00-07:00", "estimated": { "time": "2024-05-17T08:37:00-07:00" } },
+                                "end": { "scheduledTime": "2024-05-17T08:50:00-07:00", "estimated": null },
+                                "from": { "name": "3rd Ave & Pine St", "lat": 47.61, "lon": -122.34, "arrival": null, "departure": null, "stop": { "gtfsId": "1:578", "code": "578" } },
+                                "to": { "name": "Destination", "lat": 47.651048, "lon": -122.347234, "arrival": null, "departure": null, "stop": null },
+                                "legGeometry": { "length": 2, "points": "_p~iF~ps|U_ulLnnqC" },
+                                "route": { "gtfsId": "1:100", "shortName": "7", "longName": null, "color": "FF0000", "textColor": "FFFFFF" },
+                                "agency": { "gtfsId": "1:1", "name": "Metro Transit" },
+                                "trip": { "gtfsId": "1:12345" },
+                                "steps": []
+                            }]
+                        }
+                    }]
+                }
+            }
+        }))
+        .unwrap();
+
+        let plan_response = response.into_plan_response().unwrap();
+        assert!(plan_response.error.is_none());
+        let itinerary = &plan_response.plan.itineraries[0];
+        assert_eq!(itinerary.start_time, 1715959860000);
+        assert_eq!(itinerary.duration, 19 * 60);
+
+        let walk_leg = &itinerary.legs[0];
+        assert_eq!(walk_leg.mode, TransitMode::Walk);
+        assert_eq!(walk_leg.agency_time_zone_offset, Some(-7 * 3600 * 1000));
+        assert_eq!(walk_leg.to.arrival, Some(1715960100000));
+        assert_eq!(walk_leg.to.extra["stopId"], "1:578");
+        assert_eq!(
+            walk_leg.steps[0].relative_direction,
+            RelativeDirection::Continue
+        );
+
+        let transit_leg = &itinerary.legs[1];
+        assert_eq!(transit_leg.mode, TransitMode::Bus);
+        assert!(transit_leg.real_time);
+        // The real-time estimate is preferred over the schedule
+        assert_eq!(transit_leg.start_time, 1715960220000);
+        assert_eq!(transit_leg.route_color.as_deref(), Some("FF0000"));
+        assert_eq!(transit_leg.extra["routeShortName"], "7");
+        assert_eq!(transit_leg.extra["agencyName"], "Metro Transit");
+    }
+
+    #[test]
+    fn convert_routing_error() {
+        let response: GraphQlResponse = serde_json::from_value(json!({
+            "data": {
+                "planConnection": {
+                    "routingErrors": [{
+                        "code": "NO_TRANSIT_CONNECTION",
+                        "description": "No transit connection was found"
+                    }],
+                    "edges": []
+                }
+            }
+        }))
+        .unwrap();
+        let plan_response = response.into_plan_response().unwrap();
+        let error = plan_response.error.unwrap();
+        assert_eq!(error.id, 404);
+        assert!(error.is_no_path());
+
+        let response: GraphQlResponse = serde_json::from_value(json!({
+            "data": null,
+            "errors": [{ "message": "Validation error" }]
+        }))
+        .unwrap();
+        assert!(response.into_plan_response().is_err());
+    }
+}
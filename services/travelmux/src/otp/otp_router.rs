@@ -10,6 +10,38 @@ pub struct OTPRouter {
     endpoint: Url,
     router_id: String,
     polygon: Polygon,
+    api: OtpApi,
+}
+
+/// Which of OTP's APIs we plan trips with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OtpApi {
+    /// The legacy REST `/plan` API
+    #[default]
+    Rest,
+    /// OTP2's GTFS GraphQL API, using `planConnection`
+    GraphQl,
+}
+
+impl OtpApi {
+    const GRAPHQL_PREFIX: &'static str = "graphql+";
+
+    /// Endpoints are specified like `http://otp.example.com/otp/routers` for the REST API, or
+    /// `graphql+http://otp.example.com/otp/routers` for the GraphQL API. Returns the API and
+    /// the endpoint without any prefix.
+    pub fn parse_endpoint(endpoint: &str) -> (Self, &str) {
+        match endpoint.strip_prefix(Self::GRAPHQL_PREFIX) {
+            Some(endpoint) => (Self::GraphQl, endpoint),
+            None => (Self::Rest, endpoint),
+        }
+    }
+}
+
+/// Where, and how, to query a particular router.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OtpPlanEndpoint {
+    pub url: Url,
+    pub api: OtpApi,
 }
 
 impl OTPRouter {
@@ -18,12 +50,30 @@ impl OTPRouter {
             endpoint,
             router_id,
             polygon,
+            api: OtpApi::default(),
         }
     }
 
+    pub fn with_api(mut self, api: OtpApi) -> Self {
+        self.api = api;
+        self
+    }
+
+    pub fn api(&self) -> OtpApi {
+        self.api
+    }
+
     pub fn polygon(&self) -> &Polygon {
         &self.polygon
     }
+
+    pub fn router_id(&self) -> &str {
+        &self.router_id
+    }
+
+    pub fn endpoint(&self) -> &Url {
+        &self.endpoint
+    }
 }
 
 #[derive(Debug)]
@@ -33,8 +83,7 @@ pub struct OTPRouterClient {
 }
 
 impl OTPRouterClient {
-    pub fn new(endpoint: Url) -> Self {
-        let http_client = reqwest::Client::new();
+    pub fn new(endpoint: Url, http_client: reqwest::Client) -> Self {
         Self {
             endpoint,
             http_client,
@@ -62,10 +111,79 @@ impl OTPRouterClient {
     pub fn router_url(router: &OTPRouter) -> Url {
         let base_path = router.endpoint.path();
         let router_id = &router.router_id;
-        let router_path = format!("{base_path}/{router_id}/plan");
+        let router_path = match router.api {
+            OtpApi::Rest => format!("{base_path}/{router_id}/plan"),
+            // OTP2 serves a single router, e.g. `/otp/routers` -> `/otp/gtfs/v1`
+            OtpApi::GraphQl => {
+                let otp_path = base_path.strip_suffix("/routers").unwrap_or(base_path);
+                format!("{otp_path}/gtfs/v1")
+            }
+        };
 
         let mut router_url = router.endpoint.clone();
         router_url.set_path(&router_path);
         router_url
     }
+
+    /// Where to request the areas reachable within some travel times.
+    pub fn isochrone_url(router: &OTPRouter) -> Url {
+        let base_path = router.endpoint.path();
+        let router_id = &router.router_id;
+        let isochrone_path = match router.api {
+            OtpApi::Rest => format!("{base_path}/{router_id}/isochrone"),
+            // Provided by OTP2's travel time sandbox feature
+            OtpApi::GraphQl => {
+                let otp_path = base_path.strip_suffix("/routers").unwrap_or(base_path);
+                format!("{otp_path}/traveltime/isochrone")
+            }
+        };
+
+        let mut isochrone_url = router.endpoint.clone();
+        isochrone_url.set_path(&isochrone_path);
+        isochrone_url
+    }
+
+    pub fn plan_endpoint(router: &OTPRouter) -> OtpPlanEndpoint {
+        OtpPlanEndpoint {
+            url: Self::router_url(router),
+            api: router.api,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wkt::TryFromWkt;
+
+    #[test]
+    fn router_url_for_api() {
+        let (api, endpoint) = OtpApi::parse_endpoint("graphql+http://otp.example.com/otp/routers");
+        assert_eq!(api, OtpApi::GraphQl);
+        let endpoint = Url::parse(endpoint).unwrap();
+        let polygon = Polygon::try_from_wkt_str("POLYGON ((0 0, 1 0, 1 1, 0 1, 0 0))").unwrap();
+        let router = OTPRouter::new(endpoint, "default".to_string(), polygon);
+
+        assert_eq!(
+            OTPRouterClient::router_url(&router).as_str(),
+            "http://otp.example.com/otp/routers/default/plan"
+        );
+        assert_eq!(
+            OTPRouterClient::isochrone_url(&router).as_str(),
+            "http://otp.example.com/otp/routers/default/isochrone"
+        );
+        let router = router.with_api(api);
+        assert_eq!(
+            OTPRouterClient::isochrone_url(&router).as_str(),
+            "http://otp.example.com/otp/traveltime/isochrone"
+        );
+        assert_eq!(
+            OTPRouterClient::router_url(&router).as_str(),
+            "http://otp.example.com/otp/gtfs/v1"
+        );
+
+        let (api, endpoint) = OtpApi::parse_endpoint("http://otp.example.com/otp/routers");
+        assert_eq!(api, OtpApi::Rest);
+        assert_eq!(endpoint, "http://otp.example.com/otp/routers");
+    }
 }
@@ -0,0 +1,62 @@
+//! Helpers shared by unit tests.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use url::Url;
+
+/// Starts a fake upstream HTTP server, returning its URL.
+///
+/// Each request is answered with the status and JSON body returned by `respond`, which is given
+/// the request line, like `POST /route HTTP/1.1`. The whole request is read before responding,
+/// so the connection is never closed while the client is still sending.
+pub(crate) fn stub_upstream(
+    respond: impl Fn(&str) -> (u16, String) + Send + Sync + 'static,
+) -> Url {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+    let respond = std::sync::Arc::new(respond);
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let respond = respond.clone();
+            std::thread::spawn(move || {
+                let _ = handle_connection(stream, respond.as_ref());
+            });
+        }
+    });
+    url
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    respond: &dyn Fn(&str) -> (u16, String),
+) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    let (status, body) = respond(request_line.trim_end());
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {status} Stubbed\r\ncontent-type: application/json;charset=utf-8\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
@@ -1,26 +1,27 @@
+use crate::util::i18n::Language;
 use crate::MeasurementSystem;
 
-pub fn format_meters(meters: f64, output_system: MeasurementSystem) -> String {
-    match output_system {
+pub fn format_meters(meters: f64, output_system: MeasurementSystem, language: Language) -> String {
+    let length = match output_system {
         MeasurementSystem::Metric => {
             if meters < 1.5 {
-                "1 meter".to_string()
+                Length::Meters(1.0)
             } else if meters < 10.0 {
-                format!("{:.0} meters", meters.round())
+                Length::Meters(meters.round())
             } else if meters < 500.0 {
                 // the nearest 10 meters
-                format!("{:.0} meters", (meters / 10.0).round() * 10.0)
+                Length::Meters((meters / 10.0).round() * 10.0)
             } else if meters < 950.0 {
                 // nearest 100 meters
-                format!("{:.0} meters", (meters / 100.0).round() * 100.0)
+                Length::Meters((meters / 100.0).round() * 100.0)
             } else if meters < 1050.0 {
-                "1 kilometer".to_string()
+                Length::Kilometers(1.0, 0)
             } else {
                 let kilometers = meters / 1000.0;
                 if meters < 9950.0 {
-                    format!("{kilometers:.1} kilometers")
+                    Length::Kilometers(kilometers, 1)
                 } else {
-                    format!("{kilometers:.0} kilometers")
+                    Length::Kilometers(kilometers, 0)
                 }
             }
         }
@@ -34,22 +35,80 @@ pub fn format_meters(meters: f64, output_system: MeasurementSystem) -> String {
                     // round to the nearest 100 feet
                     feet = (feet / 100.0).round() * 100.0;
                 }
-                format!("{:.0} feet", (10.0f64).max(feet))
+                Length::Feet((10.0f64).max(feet))
             } else if meters < METERS_PER_MILE * 0.375 {
-                "a quarter mile".to_string()
+                Length::QuarterMile
             } else if meters < METERS_PER_MILE * 0.625 {
-                "a half mile".to_string()
+                Length::HalfMile
             } else {
                 let miles = meters / METERS_PER_MILE;
                 if (0.95..1.1).contains(&miles) {
-                    "1 mile".to_string()
+                    Length::Miles(1.0, 0)
                 } else if miles < 9.95 {
-                    format!("{miles:.1} miles")
+                    Length::Miles(miles, 1)
                 } else {
-                    format!("{:.0} miles", miles.round())
+                    Length::Miles(miles.round(), 0)
                 }
             }
         }
+    };
+    length.format(language)
+}
+
+/// A rounded length, ready to be put into words.
+enum Length {
+    Meters(f64),
+    /// The number of kilometers, and how many decimals to show
+    Kilometers(f64, usize),
+    Feet(f64),
+    QuarterMile,
+    HalfMile,
+    /// The number of miles, and how many decimals to show
+    Miles(f64, usize),
+}
+
+impl Length {
+    fn format(&self, language: Language) -> String {
+        let (value, decimals) = match *self {
+            Length::QuarterMile => {
+                return match language {
+                    Language::English => "a quarter mile",
+                    Language::German => "eine viertel Meile",
+                    Language::Spanish => "un cuarto de milla",
+                }
+                .to_string()
+            }
+            Length::HalfMile => {
+                return match language {
+                    Language::English => "a half mile",
+                    Language::German => "eine halbe Meile",
+                    Language::Spanish => "media milla",
+                }
+                .to_string()
+            }
+            Length::Meters(value) | Length::Feet(value) => (value, 0),
+            Length::Kilometers(value, decimals) | Length::Miles(value, decimals) => {
+                (value, decimals)
+            }
+        };
+        let number = language.format_number(value, decimals);
+        let singular = number == "1";
+        let unit = match (self, language) {
+            (Length::Meters(_), Language::English) => ["meter", "meters"],
+            (Length::Meters(_), Language::German) => ["Meter", "Meter"],
+            (Length::Meters(_), Language::Spanish) => ["metro", "metros"],
+            (Length::Kilometers(..), Language::English) => ["kilometer", "kilometers"],
+            (Length::Kilometers(..), Language::German) => ["Kilometer", "Kilometer"],
+            (Length::Kilometers(..), Language::Spanish) => ["kilómetro", "kilómetros"],
+            (Length::Feet(_), Language::English) => ["foot", "feet"],
+            (Length::Feet(_), Language::German) => ["Fuß", "Fuß"],
+            (Length::Feet(_), Language::Spanish) => ["pie", "pies"],
+            (Length::Miles(..), Language::English) => ["mile", "miles"],
+            (Length::Miles(..), Language::German) => ["Meile", "Meilen"],
+            (Length::Miles(..), Language::Spanish) => ["milla", "millas"],
+            (Length::QuarterMile | Length::HalfMile, _) => unreachable!("handled above"),
+        }[if singular { 0 } else { 1 }];
+        format!("{number} {unit}")
     }
 }
 
@@ -59,96 +118,142 @@ mod tests {
 
     #[test]
     fn meter_formatting() {
-        assert_eq!(format_meters(1.0, MeasurementSystem::Metric), "1 meter");
-        assert_eq!(format_meters(2.6, MeasurementSystem::Metric), "3 meters");
-        assert_eq!(format_meters(99.0, MeasurementSystem::Metric), "100 meters");
         assert_eq!(
-            format_meters(599.0, MeasurementSystem::Metric),
+            format_meters(1.0, MeasurementSystem::Metric, Language::English),
+            "1 meter"
+        );
+        assert_eq!(
+            format_meters(2.6, MeasurementSystem::Metric, Language::English),
+            "3 meters"
+        );
+        assert_eq!(
+            format_meters(99.0, MeasurementSystem::Metric, Language::English),
+            "100 meters"
+        );
+        assert_eq!(
+            format_meters(599.0, MeasurementSystem::Metric, Language::English),
             "600 meters"
         );
         assert_eq!(
-            format_meters(600.0, MeasurementSystem::Metric),
+            format_meters(600.0, MeasurementSystem::Metric, Language::English),
             "600 meters"
         );
         assert_eq!(
-            format_meters(900.0, MeasurementSystem::Metric),
+            format_meters(900.0, MeasurementSystem::Metric, Language::English),
             "900 meters"
         );
         assert_eq!(
-            format_meters(960.0, MeasurementSystem::Metric),
+            format_meters(960.0, MeasurementSystem::Metric, Language::English),
             "1 kilometer"
         );
         assert_eq!(
-            format_meters(1049.0, MeasurementSystem::Metric),
+            format_meters(1049.0, MeasurementSystem::Metric, Language::English),
             "1 kilometer"
         );
         assert_eq!(
-            format_meters(1100.0, MeasurementSystem::Metric),
+            format_meters(1100.0, MeasurementSystem::Metric, Language::English),
             "1.1 kilometers"
         );
         assert_eq!(
-            format_meters(9940.0, MeasurementSystem::Metric),
+            format_meters(9940.0, MeasurementSystem::Metric, Language::English),
             "9.9 kilometers"
         );
         assert_eq!(
-            format_meters(9999.0, MeasurementSystem::Metric),
+            format_meters(9999.0, MeasurementSystem::Metric, Language::English),
             "10 kilometers"
         );
         assert_eq!(
-            format_meters(10000.0, MeasurementSystem::Metric),
+            format_meters(10000.0, MeasurementSystem::Metric, Language::English),
             "10 kilometers"
         );
         assert_eq!(
-            format_meters(100000.0, MeasurementSystem::Metric),
+            format_meters(100000.0, MeasurementSystem::Metric, Language::English),
             "100 kilometers"
         );
     }
 
     #[test]
     fn format_miles_from_meters() {
-        assert_eq!(format_meters(1.0, MeasurementSystem::Imperial), "10 feet");
-        assert_eq!(format_meters(10.0, MeasurementSystem::Imperial), "30 feet");
-        assert_eq!(format_meters(50.0, MeasurementSystem::Imperial), "160 feet");
         assert_eq!(
-            format_meters(100.0, MeasurementSystem::Imperial),
+            format_meters(1.0, MeasurementSystem::Imperial, Language::English),
+            "10 feet"
+        );
+        assert_eq!(
+            format_meters(10.0, MeasurementSystem::Imperial, Language::English),
+            "30 feet"
+        );
+        assert_eq!(
+            format_meters(50.0, MeasurementSystem::Imperial, Language::English),
+            "160 feet"
+        );
+        assert_eq!(
+            format_meters(100.0, MeasurementSystem::Imperial, Language::English),
             "300 feet"
         );
         assert_eq!(
-            format_meters(500.0, MeasurementSystem::Imperial),
+            format_meters(500.0, MeasurementSystem::Imperial, Language::English),
             "a quarter mile"
         );
         assert_eq!(
-            format_meters(1000.0, MeasurementSystem::Imperial),
+            format_meters(1000.0, MeasurementSystem::Imperial, Language::English),
             "a half mile"
         );
         assert_eq!(
-            format_meters(1100.0, MeasurementSystem::Imperial),
+            format_meters(1100.0, MeasurementSystem::Imperial, Language::English),
             "0.7 miles"
         );
         assert_eq!(
-            format_meters(1300.0, MeasurementSystem::Imperial),
+            format_meters(1300.0, MeasurementSystem::Imperial, Language::English),
             "0.8 miles"
         );
-        assert_eq!(format_meters(1700.0, MeasurementSystem::Imperial), "1 mile");
         assert_eq!(
-            format_meters(1800.0, MeasurementSystem::Imperial),
+            format_meters(1700.0, MeasurementSystem::Imperial, Language::English),
+            "1 mile"
+        );
+        assert_eq!(
+            format_meters(1800.0, MeasurementSystem::Imperial, Language::English),
             "1.1 miles"
         );
         assert_eq!(
-            format_meters(2000.0, MeasurementSystem::Imperial),
+            format_meters(2000.0, MeasurementSystem::Imperial, Language::English),
             "1.2 miles"
         );
         assert_eq!(
-            format_meters(16000.0, MeasurementSystem::Imperial),
+            format_meters(16000.0, MeasurementSystem::Imperial, Language::English),
             "9.9 miles"
         );
         assert_eq!(
-            format_meters(16500.0, MeasurementSystem::Imperial),
+            format_meters(16500.0, MeasurementSystem::Imperial, Language::English),
             "10 miles"
         );
         assert_eq!(
-            format_meters(20000.0, MeasurementSystem::Imperial),
+            format_meters(20000.0, MeasurementSystem::Imperial, Language::English),
             "12 miles"
         );
     }
+
+    #[test]
+    fn localized_formatting() {
+        let metric = MeasurementSystem::Metric;
+        let imperial = MeasurementSystem::Imperial;
+        assert_eq!(format_meters(1.0, metric, Language::German), "1 Meter");
+        assert_eq!(format_meters(99.0, metric, Language::Spanish), "100 metros");
+        assert_eq!(
+            format_meters(1100.0, metric, Language::German),
+            "1,1 Kilometer"
+        );
+        assert_eq!(
+            format_meters(1000.0, metric, Language::Spanish),
+            "1 kilómetro"
+        );
+        assert_eq!(format_meters(50.0, imperial, Language::German), "160 Fuß");
+        assert_eq!(
+            format_meters(1000.0, imperial, Language::Spanish),
+            "media milla"
+        );
+        assert_eq!(
+            format_meters(2000.0, imperial, Language::German),
+            "1,2 Meilen"
+        );
+    }
 }
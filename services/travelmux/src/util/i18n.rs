@@ -0,0 +1,387 @@
+//! Messages for the instructions we write ourselves, e.g. for OTP routes.
+//! Valhalla localizes its own instructions.
+
+/// The languages we have messages for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Language {
+    #[default]
+    English,
+    German,
+    Spanish,
+}
+
+/// How the traveler is moving when they depart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Travel {
+    Walk,
+    Bike,
+    Drive,
+    Transit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compass {
+    North,
+    Northeast,
+    East,
+    Southeast,
+    South,
+    Southwest,
+    West,
+    Northwest,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Turn {
+    SharpLeft,
+    Left,
+    SlightLeft,
+    SlightRight,
+    Right,
+    SharpRight,
+}
+
+impl Language {
+    /// Parses an IETF language tag like `de` or `de-AT`, falling back to English for languages
+    /// we don't have messages for.
+    pub fn from_tag(tag: &str) -> Self {
+        let primary = tag.split(['-', '_']).next().unwrap_or_default();
+        match primary.to_ascii_lowercase().as_str() {
+            "de" => Language::German,
+            "es" => Language::Spanish,
+            _ => Language::English,
+        }
+    }
+
+    /// `street_name` is `None` when it isn't worth mentioning, e.g. a generated name like "path"
+    /// which we've already mentioned.
+    pub fn depart(&self, travel: Travel, direction: Compass, street_name: Option<&str>) -> String {
+        let direction = self.compass(direction);
+        match self {
+            Language::English => {
+                let travel = match travel {
+                    Travel::Walk => "Walk",
+                    Travel::Bike => "Bike",
+                    Travel::Drive => "Drive",
+                    Travel::Transit => "Transit",
+                };
+                match street_name {
+                    Some(street_name) => format!("{travel} {direction} on {street_name}."),
+                    None => format!("{travel} {direction}."),
+                }
+            }
+            Language::German => {
+                let travel = match travel {
+                    Travel::Walk => "Gehen",
+                    Travel::Bike => "Radeln",
+                    Travel::Drive | Travel::Transit => "Fahren",
+                };
+                match street_name {
+                    Some(street_name) => {
+                        format!("{travel} Sie Richtung {direction} auf {street_name}.")
+                    }
+                    None => format!("{travel} Sie Richtung {direction}."),
+                }
+            }
+            Language::Spanish => {
+                let travel = match travel {
+                    Travel::Walk => "Camine",
+                    Travel::Bike => "Pedalee",
+                    Travel::Drive => "Conduzca",
+                    Travel::Transit => "Viaje",
+                };
+                match street_name {
+                    Some(street_name) => {
+                        format!("{travel} hacia el {direction} por {street_name}.")
+                    }
+                    None => format!("{travel} hacia el {direction}."),
+                }
+            }
+        }
+    }
+
+    pub fn depart_without_direction(&self) -> String {
+        match self {
+            Language::English => "Depart.",
+            Language::German => "Starten Sie.",
+            Language::Spanish => "Salga.",
+        }
+        .to_string()
+    }
+
+    /// When `stay_on` is set, the street changes direction, so the turn keeps the traveler on
+    /// `street_name` rather than turning onto it.
+    pub fn turn(&self, turn: Turn, street_name: Option<&str>, stay_on: bool) -> String {
+        let slight = matches!(turn, Turn::SlightLeft | Turn::SlightRight);
+        match self {
+            Language::English => {
+                let turn = match turn {
+                    Turn::SharpLeft => "sharp left",
+                    Turn::Left => "left",
+                    Turn::SlightLeft if stay_on => "left",
+                    Turn::SlightLeft => "slightly left",
+                    Turn::SlightRight if stay_on => "right",
+                    Turn::SlightRight => "slightly right",
+                    Turn::Right => "right",
+                    Turn::SharpRight => "sharp right",
+                };
+                match street_name {
+                    Some(street_name) if stay_on && slight => {
+                        format!("Bear {turn} to stay on {street_name}.")
+                    }
+                    Some(street_name) if stay_on => {
+                        format!("Turn {turn} to stay on {street_name}.")
+                    }
+                    Some(street_name) => format!("Turn {turn} onto {street_name}."),
+                    None => format!("Turn {turn}."),
+                }
+            }
+            Language::German => {
+                let side = match turn {
+                    Turn::SharpLeft | Turn::Left | Turn::SlightLeft => "links",
+                    Turn::SlightRight | Turn::Right | Turn::SharpRight => "rechts",
+                };
+                let turn = match turn {
+                    Turn::SharpLeft => "scharf links",
+                    Turn::Left => "links",
+                    Turn::SlightLeft => "leicht links",
+                    Turn::SlightRight => "leicht rechts",
+                    Turn::Right => "rechts",
+                    Turn::SharpRight => "scharf rechts",
+                };
+                match street_name {
+                    Some(street_name) if stay_on && slight => {
+                        format!("Halten Sie sich {side}, um auf {street_name} zu bleiben.")
+                    }
+                    Some(street_name) if stay_on => {
+                        format!("Biegen Sie {turn} ab, um auf {street_name} zu bleiben.")
+                    }
+                    Some(street_name) => format!("Biegen Sie {turn} in {street_name} ab."),
+                    None => format!("Biegen Sie {turn} ab."),
+                }
+            }
+            Language::Spanish => {
+                let side = match turn {
+                    Turn::SharpLeft | Turn::Left | Turn::SlightLeft => "a la izquierda",
+                    Turn::SlightRight | Turn::Right | Turn::SharpRight => "a la derecha",
+                };
+                let turn = match turn {
+                    Turn::SharpLeft => "bruscamente a la izquierda",
+                    Turn::Left => "a la izquierda",
+                    Turn::SlightLeft => "ligeramente a la izquierda",
+                    Turn::SlightRight => "ligeramente a la derecha",
+                    Turn::Right => "a la derecha",
+                    Turn::SharpRight => "bruscamente a la derecha",
+                };
+                match street_name {
+                    Some(street_name) if stay_on && slight => {
+                        format!("Manténgase {side} para seguir en {street_name}.")
+                    }
+                    Some(street_name) if stay_on => {
+                        format!("Gire {turn} para seguir en {street_name}.")
+                    }
+                    Some(street_name) => format!("Gire {turn} en {street_name}."),
+                    None => format!("Gire {turn}."),
+                }
+            }
+        }
+    }
+
+    pub fn continue_onto(&self, street_name: Option<&str>, stay_on: bool) -> String {
+        match (self, street_name) {
+            (Language::English, Some(street_name)) if stay_on => {
+                format!("Continue on {street_name}.")
+            }
+            (Language::English, Some(street_name)) => format!("Continue onto {street_name}."),
+            (Language::English, None) => "Continue.".to_string(),
+            (Language::German, Some(street_name)) if stay_on => {
+                format!("Bleiben Sie auf {street_name}.")
+            }
+            (Language::German, Some(street_name)) => format!("Weiter auf {street_name}."),
+            (Language::German, None) => "Weiter.".to_string(),
+            (Language::Spanish, Some(street_name)) if stay_on => {
+                format!("Siga por {street_name}.")
+            }
+            (Language::Spanish, Some(street_name)) => format!("Continúe por {street_name}."),
+            (Language::Spanish, None) => "Continúe.".to_string(),
+        }
+    }
+
+    /// For open areas like plazas or platforms, where there's no street to follow.
+    pub fn cross(&self, area_name: Option<&str>) -> String {
+        match (self, area_name) {
+            (Language::English, Some(area_name)) => format!("Cross {area_name}."),
+            (Language::English, None) => "Cross the open area.".to_string(),
+            (Language::German, Some(area_name)) => format!("Überqueren Sie {area_name}."),
+            (Language::German, None) => "Überqueren Sie den Platz.".to_string(),
+            (Language::Spanish, Some(area_name)) => format!("Cruce {area_name}."),
+            (Language::Spanish, None) => "Cruce la plaza.".to_string(),
+        }
+    }
+
+    pub fn enter_roundabout(&self) -> String {
+        match self {
+            Language::English => "Enter the roundabout.",
+            Language::German => "Fahren Sie in den Kreisverkehr ein.",
+            Language::Spanish => "Entre en la rotonda.",
+        }
+        .to_string()
+    }
+
+    pub fn take_roundabout_exit(&self, exit: &str) -> String {
+        match self {
+            Language::English => format!("Take exit {exit} at the roundabout."),
+            Language::German => format!("Nehmen Sie im Kreisverkehr die Ausfahrt {exit}."),
+            Language::Spanish => format!("En la rotonda, tome la salida {exit}."),
+        }
+    }
+
+    pub fn take_exit(&self, exit: &str, street_name: Option<&str>) -> String {
+        match (self, street_name) {
+            (Language::English, Some(street_name)) => {
+                format!("Take exit {exit} onto {street_name}.")
+            }
+            (Language::English, None) => format!("Take exit {exit}."),
+            (Language::German, Some(street_name)) => {
+                format!("Nehmen Sie die Ausfahrt {exit} auf {street_name}.")
+            }
+            (Language::German, None) => format!("Nehmen Sie die Ausfahrt {exit}."),
+            (Language::Spanish, Some(street_name)) => {
+                format!("Tome la salida {exit} hacia {street_name}.")
+            }
+            (Language::Spanish, None) => format!("Tome la salida {exit}."),
+        }
+    }
+
+    pub fn enter_elevator(&self) -> String {
+        match self {
+            Language::English => "Enter the elevator.",
+            Language::German => "Nehmen Sie den Aufzug.",
+            Language::Spanish => "Tome el ascensor.",
+        }
+        .to_string()
+    }
+
+    pub fn make_u_turn(&self) -> String {
+        match self {
+            Language::English => "Make a U-turn.",
+            Language::German => "Wenden Sie.",
+            Language::Spanish => "Haga un cambio de sentido.",
+        }
+        .to_string()
+    }
+
+    pub fn arrive(&self) -> String {
+        match self {
+            Language::English => "Arrive at your destination.",
+            Language::German => "Sie haben Ihr Ziel erreicht.",
+            Language::Spanish => "Ha llegado a su destino.",
+        }
+        .to_string()
+    }
+
+    /// e.g. "Continue for 60 feet." where `distance` has been formatted by `format_meters`.
+    pub fn continue_for(&self, distance: &str) -> String {
+        match self {
+            Language::English => format!("Continue for {distance}."),
+            Language::German => format!("Weiter für {distance}."),
+            Language::Spanish => format!("Continúe durante {distance}."),
+        }
+    }
+
+    fn compass(&self, direction: Compass) -> &'static str {
+        match self {
+            Language::English => match direction {
+                Compass::North => "north",
+                Compass::Northeast => "northeast",
+                Compass::East => "east",
+                Compass::Southeast => "southeast",
+                Compass::South => "south",
+                Compass::Southwest => "southwest",
+                Compass::West => "west",
+                Compass::Northwest => "northwest",
+            },
+            Language::German => match direction {
+                Compass::North => "Norden",
+                Compass::Northeast => "Nordosten",
+                Compass::East => "Osten",
+                Compass::Southeast => "Südosten",
+                Compass::South => "Süden",
+                Compass::Southwest => "Südwesten",
+                Compass::West => "Westen",
+                Compass::Northwest => "Nordwesten",
+            },
+            Language::Spanish => match direction {
+                Compass::North => "norte",
+                Compass::Northeast => "noreste",
+                Compass::East => "este",
+                Compass::Southeast => "sureste",
+                Compass::South => "sur",
+                Compass::Southwest => "suroeste",
+                Compass::West => "oeste",
+                Compass::Northwest => "noroeste",
+            },
+        }
+    }
+
+    /// Formats a number with `decimals` digits after the decimal separator.
+    pub(crate) fn format_number(&self, value: f64, decimals: usize) -> String {
+        let formatted = format!("{value:.decimals$}");
+        match self {
+            Language::English => formatted,
+            Language::German | Language::Spanish => formatted.replace('.', ","),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_language_tag() {
+        assert_eq!(Language::from_tag("de"), Language::German);
+        assert_eq!(Language::from_tag("de-AT"), Language::German);
+        assert_eq!(Language::from_tag("ES_mx"), Language::Spanish);
+        assert_eq!(Language::from_tag("en-US"), Language::English);
+        // We don't have French messages
+        assert_eq!(Language::from_tag("fr"), Language::English);
+    }
+
+    #[test]
+    fn messages() {
+        let street = "Main Street";
+        assert_eq!(
+            Language::English.depart(Travel::Bike, Compass::Northeast, Some(street)),
+            "Bike northeast on Main Street."
+        );
+        assert_eq!(
+            Language::German.depart(Travel::Walk, Compass::South, Some(street)),
+            "Gehen Sie Richtung Süden auf Main Street."
+        );
+        assert_eq!(
+            Language::Spanish.turn(Turn::SlightRight, Some(street), false),
+            "Gire ligeramente a la derecha en Main Street."
+        );
+        assert_eq!(
+            Language::English.turn(Turn::SlightRight, Some(street), true),
+            "Bear right to stay on Main Street."
+        );
+        assert_eq!(
+            Language::German.turn(Turn::Left, Some(street), true),
+            "Biegen Sie links ab, um auf Main Street zu bleiben."
+        );
+        assert_eq!(
+            Language::English.turn(Turn::Left, None, false),
+            "Turn left."
+        );
+        assert_eq!(
+            Language::Spanish.take_roundabout_exit("3"),
+            "En la rotonda, tome la salida 3."
+        );
+        assert_eq!(
+            Language::German.continue_for("1,5 Kilometer"),
+            "Weiter für 1,5 Kilometer."
+        );
+    }
+}
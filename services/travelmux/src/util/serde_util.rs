@@ -1,4 +1,4 @@
-use geo::{Point, Rect};
+use geo::{Geometry, Point, Polygon, Rect};
 use serde::ser::{Error, SerializeStruct, SerializeTuple};
 use serde::{Deserialize, Deserializer, Serializer};
 use std::time::SystemTime;
@@ -10,27 +10,116 @@ where
     use serde::de::Error;
 
     let s: String = Deserialize::deserialize(deserializer)?;
+    parse_point_from_lat_lon(&s).map_err(D::Error::custom)
+}
+
+/// Like `deserialize_point_from_lat_lon`, for use with `#[serde(default)]`.
+pub fn deserialize_optional_point_from_lat_lon<'de, D>(
+    deserializer: D,
+) -> Result<Option<Point>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_point_from_lat_lon(deserializer).map(Some)
+}
+
+/// Deserializes a `|` separated list of `lat,lon` pairs, e.g. `47.6,-122.3|47.7,-122.4`
+pub fn deserialize_points_from_lat_lon_list<'de, D>(deserializer: D) -> Result<Vec<Point>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+
+    let s: String = Deserialize::deserialize(deserializer)?;
+    if s.is_empty() {
+        return Ok(vec![]);
+    }
+    s.split('|')
+        .map(|lat_lon| parse_point_from_lat_lon(lat_lon).map_err(D::Error::custom))
+        .collect()
+}
+
+/// Deserializes polygons from either a GeoJSON Polygon or MultiPolygon (or a Feature or
+/// FeatureCollection of them), or from polyline6 encoded rings separated by `,`.
+pub fn deserialize_polygons_from_geojson_or_polyline6<'de, D>(
+    deserializer: D,
+) -> Result<Vec<Polygon>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+
+    let s: String = Deserialize::deserialize(deserializer)?;
+    parse_polygons_from_geojson_or_polyline6(&s).map_err(D::Error::custom)
+}
+
+fn parse_polygons_from_geojson_or_polyline6(s: &str) -> Result<Vec<Polygon>, String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let polygons = if s.starts_with('{') {
+        let geojson: geojson::GeoJson = s.parse().map_err(|e| format!("invalid GeoJSON: {e}"))?;
+        let geometry =
+            Geometry::try_from(geojson).map_err(|e| format!("invalid GeoJSON geometry: {e}"))?;
+        let mut polygons = vec![];
+        collect_polygons(geometry, &mut polygons)?;
+        polygons
+    } else {
+        s.split(',')
+            .map(|encoded| {
+                polyline::decode_polyline(encoded, 6)
+                    .map(|ring| Polygon::new(ring, vec![]))
+                    .map_err(|e| format!("invalid polyline: {e}"))
+            })
+            .collect::<Result<Vec<_>, _>>()?
+    };
+
+    for polygon in &polygons {
+        // rings are closed, so a triangle has 4 coords
+        if polygon.exterior().0.len() < 4 {
+            return Err("a polygon needs at least 3 points".to_string());
+        }
+    }
+    Ok(polygons)
+}
+
+fn collect_polygons(geometry: Geometry, polygons: &mut Vec<Polygon>) -> Result<(), String> {
+    match geometry {
+        Geometry::Polygon(polygon) => polygons.push(polygon),
+        Geometry::MultiPolygon(multi_polygon) => polygons.extend(multi_polygon),
+        Geometry::GeometryCollection(collection) => {
+            for geometry in collection {
+                collect_polygons(geometry, polygons)?;
+            }
+        }
+        _ => return Err("expected a Polygon or MultiPolygon".to_string()),
+    }
+    Ok(())
+}
+
+fn parse_point_from_lat_lon(s: &str) -> Result<Point, String> {
     use std::str::FromStr;
     let mut iter = s.split(',').map(f64::from_str);
 
     let Some(lat_res) = iter.next() else {
-        return Err(D::Error::custom("missing lat"));
+        return Err("missing lat".to_string());
     };
-    let lat = lat_res.map_err(|e| D::Error::custom(format!("invalid lat: {e}")))?;
+    let lat = lat_res.map_err(|e| format!("invalid lat: {e}"))?;
 
     let Some(lon_res) = iter.next() else {
-        return Err(D::Error::custom("missing lon"));
+        return Err("missing lon".to_string());
     };
-    let lon = lon_res.map_err(|e| D::Error::custom(format!("invalid lon: {e}")))?;
+    let lon = lon_res.map_err(|e| format!("invalid lon: {e}"))?;
 
     if let Some(next) = iter.next() {
-        return Err(D::Error::custom(format!(
-            "found an extra param in lat,lon,???: {next:?}"
-        )));
+        return Err(format!("found an extra param in lat,lon,???: {next:?}"));
     }
 
     Ok(Point::new(lon, lat))
 }
+
 pub fn serialize_point_as_lon_lat_pair<S>(point: &Point, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
@@ -5,7 +5,7 @@ use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
 use std::collections::HashMap;
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum ModeCosting {
     Auto,
@@ -13,14 +13,253 @@ pub enum ModeCosting {
     Pedestrian,
 }
 
-/// Parameters for a query, as in:
-///     `route?json={%22locations%22:[{%22lat%22:47.575837,%22lon%22:-122.339414},{%22lat%22:47.651048,%22lon%22:-122.347234}],%22costing%22:%22auto%22,%22alternates%22:3,%22units%22:%22miles%22}`
+/// Response from `/status`. Most fields are only present if `verbose` was requested.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct StatusResponse {
+    pub version: Option<String>,
+    /// The extent of the routing graph
+    pub bbox: Option<geojson::GeoJson>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+/// The body of a `POST /route` request, as in:
+///     `{"locations":[{"lat":47.575837,"lon":-122.339414},{"lat":47.651048,"lon":-122.347234}],"costing":"auto","alternates":3,"units":"miles"}`
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ValhallaRouteQuery {
     pub locations: Vec<LonLat>,
     pub costing: ModeCosting,
+    /// Keyed by the costing model they apply to, e.g. `{"bicycle": {"use_hills": 0.1}}`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub costing_options: HashMap<ModeCosting, CostingOptions>,
+    /// Rings of `[lon, lat]` pairs, whose roads won't be used
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_polygons: Vec<Vec<[f64; 2]>>,
+    /// Points whose nearest roads won't be used
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_locations: Vec<LonLat>,
     pub alternates: u32,
     pub units: DistanceUnit,
+    /// The language of the turn-by-turn instructions, like `de-DE`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date_time: Option<DateTime>,
+}
+
+/// Tweaks to how a costing model weighs roads. Each costing model only supports some of these,
+/// see <https://valhalla.github.io/valhalla/api/turn-by-turn/api-reference/#costing-options>
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CostingOptions {
+    /// From 0 (avoid hills) to 1 (don't mind hills). `bicycle` and `pedestrian` only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub use_hills: Option<f64>,
+    /// From 0 (avoid ferries) to 1 (prefer ferries).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub use_ferry: Option<f64>,
+    /// From 0 (avoid highways) to 1 (prefer highways). `auto` only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub use_highways: Option<f64>,
+    /// `bicycle` only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bicycle_type: Option<BicycleType>,
+    /// The steepest grade to allow, in percent. `pedestrian` only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_grade: Option<u32>,
+    /// The fastest the vehicle will go, in km/h. `auto` only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_speed: Option<f64>,
+    /// The average cycling speed, in km/h. `bicycle` only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cycling_speed: Option<f64>,
+    /// `pedestrian` only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub r#type: Option<PedestrianType>,
+}
+
+impl CostingOptions {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PedestrianType {
+    Foot,
+    /// Avoids steps and steep grades
+    Wheelchair,
+    Blind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BicycleType {
+    Road,
+    Hybrid,
+    City,
+    Cross,
+    Mountain,
+}
+
+/// The body of a `POST /isochrone` request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IsochroneQuery {
+    pub locations: Vec<LonLat>,
+    pub costing: ModeCosting,
+    pub contours: Vec<Contour>,
+    /// Return polygons rather than linestrings
+    pub polygons: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Contour {
+    /// In minutes
+    pub time: f64,
+}
+
+/// Isochrones are returned as GeoJSON, with each feature's `contour` property in minutes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum IsochroneResponseResult {
+    Ok(geojson::FeatureCollection),
+    Err(RouteResponseError),
+}
+
+/// The body of a `POST /trace_route` request. The response is shaped like a `/route` response.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TraceRouteQuery {
+    /// The recorded trace, in order
+    pub shape: Vec<LonLat>,
+    pub costing: ModeCosting,
+    pub shape_match: ShapeMatch,
+    pub units: DistanceUnit,
+    /// The language of the turn-by-turn instructions, like `de-DE`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShapeMatch {
+    /// The shape already follows the road network exactly
+    EdgeWalk,
+    /// Snap a noisy shape, like a GPS trace, onto the road network
+    MapSnap,
+    /// Try `EdgeWalk`, falling back to `MapSnap`
+    WalkOrSnap,
+}
+
+/// The body of a `POST /optimized_route` request. The first and last locations stay in place,
+/// and the response is shaped like a `/route` response.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OptimizedRouteQuery {
+    pub locations: Vec<LonLat>,
+    pub costing: ModeCosting,
+    pub units: DistanceUnit,
+    /// The language of the turn-by-turn instructions, like `de-DE`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+}
+
+/// The body of a `POST /locate` request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LocateQuery {
+    pub locations: Vec<LonLat>,
+    pub costing: ModeCosting,
+    /// Include details like street names
+    pub verbose: bool,
+}
+
+/// `/locate` responds with one of these for each requested location.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Located {
+    pub input_lat: f64,
+    pub input_lon: f64,
+    /// The roads the location could be snapped onto, or `None` if there are none nearby
+    pub edges: Option<Vec<LocatedEdge>>,
+
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LocatedEdge {
+    /// Where on the road the location snaps to
+    pub correlated_lat: f64,
+    pub correlated_lon: f64,
+    /// Only present for `verbose` requests
+    pub edge_info: Option<EdgeInfo>,
+
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EdgeInfo {
+    #[serde(default)]
+    pub names: Vec<String>,
+
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum LocateResponseResult {
+    Ok(Vec<Located>),
+    Err(RouteResponseError),
+}
+
+/// The body of a `POST /sources_to_targets` request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MatrixQuery {
+    pub sources: Vec<LonLat>,
+    pub targets: Vec<LonLat>,
+    pub costing: ModeCosting,
+    pub units: DistanceUnit,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MatrixResponse {
+    /// Indexed by source, then by target
+    pub sources_to_targets: Vec<Vec<MatrixCell>>,
+    pub units: DistanceUnit,
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+/// `distance` and `time` are missing when there's no route from the source to the target.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MatrixCell {
+    pub distance: Option<f64>,
+    /// In seconds
+    pub time: Option<f64>,
+    pub from_index: usize,
+    pub to_index: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MatrixResponseResult {
+    Ok(MatrixResponse),
+    Err(RouteResponseError),
+}
+
+/// When the trip should take place, in local time at the origin (or at the destination for
+/// `ArriveBy`).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DateTime {
+    pub r#type: DateTimeType,
+    /// Formatted as `YYYY-MM-DDTHH:MM`
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize_repr, Deserialize_repr)]
+#[repr(u8)]
+pub enum DateTimeType {
+    Current = 0,
+    DepartAt = 1,
+    ArriveBy = 2,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -33,6 +272,15 @@ pub struct RouteResponseError {
     pub extra: HashMap<String, serde_json::Value>,
 }
 
+impl RouteResponseError {
+    /// Valhalla couldn't find a route, as opposed to e.g. rejecting the request.
+    pub fn is_no_path(&self) -> bool {
+        // 171: No suitable edges near location
+        // 442: No path could be found for input
+        matches!(self.error_code, 171 | 442)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct RouteResponse {
     pub trip: Trip,
@@ -48,7 +296,7 @@ pub enum ValhallaRouteResponseResult {
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Trip {
-    pub locations: Vec<LonLat>,
+    pub locations: Vec<TripLocation>,
     pub summary: Summary,
     pub units: DistanceUnit, // legs: Vec<Leg>
     pub legs: Vec<Leg>,
@@ -75,6 +323,24 @@ pub struct Summary {
     pub extra: HashMap<String, serde_json::Value>,
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TripLocation {
+    pub lon: f64,
+    pub lat: f64,
+    /// Local time at the location, formatted as `YYYY-MM-DDTHH:MM`.
+    /// Only present when the request specified a `date_time`.
+    pub date_time: Option<String>,
+    /// Offset of the local time from UTC, formatted as e.g. `-07:00`.
+    /// Only present when the request specified a `date_time`.
+    pub time_zone_offset: Option<String>,
+    /// The location's index in the request. Only differs from its index in the response for
+    /// `/optimized_route`, which reorders the locations.
+    pub original_index: Option<usize>,
+
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct LonLat {
     pub lon: f64,
@@ -183,6 +449,15 @@ impl From<Point> for LonLat {
     }
 }
 
+impl From<&TripLocation> for LonLat {
+    fn from(value: &TripLocation) -> Self {
+        Self {
+            lon: value.lon,
+            lat: value.lat,
+        }
+    }
+}
+
 impl From<LonLat> for Point {
     fn from(value: LonLat) -> Self {
         geo::point!(x: value.lon, y: value.lat)
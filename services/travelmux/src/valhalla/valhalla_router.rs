@@ -1,8 +1,8 @@
 use geo::Point;
 use url::Url;
 
-use super::valhalla_api::{ModeCosting, ValhallaRouteQuery};
-use crate::{DistanceUnit, Result};
+use super::valhalla_api::{DateTime, LonLat, ModeCosting, ValhallaRouteQuery};
+use crate::DistanceUnit;
 
 #[derive(Debug, Clone)]
 pub struct ValhallaRouter {
@@ -14,29 +14,89 @@ impl ValhallaRouter {
         Self { endpoint }
     }
 
-    pub fn plan_url(
+    /// Where to `POST` a `ValhallaRouteQuery`
+    pub fn route_url(&self) -> Url {
+        let mut url = self.endpoint.clone();
+        url.set_path("/route");
+        url
+    }
+
+    pub fn route_query(
         &self,
-        source: Point,
-        destination: Point,
+        locations: &[Point],
         mode: ModeCosting,
         num_itineraries: u32,
         distance_units: DistanceUnit,
-    ) -> Result<Url> {
-        let mut url = self.endpoint.clone();
+        date_time: Option<DateTime>,
+    ) -> ValhallaRouteQuery {
+        debug_assert!(locations.len() >= 2, "a route needs at least two locations");
+        // Valhalla only computes alternate routes between exactly two locations
+        let alternates = if locations.len() == 2 {
+            num_itineraries
+        } else {
+            0
+        };
 
-        let query = ValhallaRouteQuery {
-            locations: vec![source.into(), destination.into()],
+        ValhallaRouteQuery {
+            locations: locations.iter().copied().map(LonLat::from).collect(),
             costing: mode,
-            alternates: num_itineraries,
+            costing_options: Default::default(),
+            exclude_polygons: vec![],
+            exclude_locations: vec![],
+            alternates,
             // NOTE: these units get embedded in the localized turn-by-turn direction strings
             units: distance_units,
-        };
+            language: None,
+            date_time,
+        }
+    }
 
-        url.set_path("/route");
+    /// Where to `POST` an `IsochroneQuery`
+    pub fn isochrone_url(&self) -> Url {
+        let mut url = self.endpoint.clone();
+        url.set_path("/isochrone");
+        url
+    }
 
-        let json_string = serde_json::to_string(&query).expect("valid json representation");
-        url.query_pairs_mut().append_pair("json", &json_string);
+    /// Where to `POST` a `LocateQuery`
+    pub fn locate_url(&self) -> Url {
+        let mut url = self.endpoint.clone();
+        url.set_path("/locate");
+        url
+    }
+
+    /// Where to `POST` a `MatrixQuery`
+    pub fn matrix_url(&self) -> Url {
+        let mut url = self.endpoint.clone();
+        url.set_path("/sources_to_targets");
+        url
+    }
+
+    /// Where to `POST` an `OptimizedRouteQuery`
+    pub fn optimized_route_url(&self) -> Url {
+        let mut url = self.endpoint.clone();
+        url.set_path("/optimized_route");
+        url
+    }
+
+    /// Where to `POST` a `TraceRouteQuery`
+    pub fn trace_route_url(&self) -> Url {
+        let mut url = self.endpoint.clone();
+        url.set_path("/trace_route");
+        url
+    }
+
+    pub fn status_url(&self) -> Url {
+        let mut url = self.endpoint.clone();
+        url.set_path("/status");
+        url
+    }
 
-        Ok(url)
+    /// Like `status_url`, but the response includes details like the graph's extent.
+    pub fn verbose_status_url(&self) -> Url {
+        let mut url = self.status_url();
+        url.query_pairs_mut()
+            .append_pair("json", r#"{"verbose":true}"#);
+        url
     }
 }